@@ -65,9 +65,9 @@ fun ConnectionSection(
         OutlinedTextField(
             value = tempIp,
             onValueChange = {
-                if (it.length <= 15) {
+                if (it.length <= 253) {
                     tempIp = it
-                    isIpError = it.isNotBlank() && !JourBackend.validateIp(it)
+                    isIpError = it.isNotBlank() && !JourBackend.validateHost(it)
                 }
             },
             modifier = Modifier
@@ -1,11 +1,96 @@
 package org.cf0x.rustnithm.Data
 
 import android.util.Log
+import kotlin.concurrent.thread
+
+/** Send-loop timing as measured by the native engine; times in nanoseconds. */
+data class Telemetry(
+    val packetsSent: Long,
+    val sendErrors: Long,
+    val missedDeadlines: Long,
+    val samples: Long,
+    val minIntervalNs: Long,
+    val maxIntervalNs: Long,
+    val meanIntervalNs: Long,
+    val p99JitterNs: Long,
+    val targetIntervalNs: Long
+)
+
+/** Round-trip estimates from ping/pong; times in nanoseconds, loss in parts per million. */
+data class Rtt(
+    val pingsSent: Long,
+    val pongsReceived: Long,
+    val pingsLost: Long,
+    val lastRttNs: Long,
+    val minRttNs: Long,
+    val smoothedRttNs: Long,
+    val rttVarNs: Long,
+    val lossPpm: Long
+)
+
+/** A server that answered a LAN discovery probe. */
+data class DiscoveredServer(
+    val host: String,
+    val port: Int,
+    val udp: Boolean,
+    val tcp: Boolean,
+    val name: String
+)
+
+/** Frames dropped in pairing mode. */
+data class AuthStats(
+    val unauthenticated: Long,
+    val replayed: Long
+)
 
 object Net {
 
+    /** Size of the buffer passed to [readLeds]: RGB for 31 slider then 6 air LEDs. */
+    const val LED_FRAME_SIZE = 3 * (31 + 6)
+
+    const val FEATURE_LED = 1 shl 0
+    const val FEATURE_PING = 1 shl 1
+    const val FEATURE_STAMP = 1 shl 2
+    const val FEATURE_FULL_STATE = 1 shl 3
+    /** Sync and card swipes over UDP are acked and retransmitted until received. */
+    const val FEATURE_RELIABLE = 1 shl 4
+    const val FEATURES_ALL =
+        FEATURE_LED or FEATURE_PING or FEATURE_STAMP or FEATURE_FULL_STATE or FEATURE_RELIABLE
+
+    /** Wire formats for [setDialect]. */
+    const val DIALECT_RUSTNITHM = 0
+    const val DIALECT_BROKENITHM = 1
+
+    /** Sweep directions for [setPulseProfile]; both = up, then back down. */
+    const val ZONE_ORDER_UP = 0
+    const val ZONE_ORDER_DOWN = 1
+    const val ZONE_ORDER_BOTH = 2
+
+    /** What a flick does while the previous flick's sweep runs, for [setRetriggerPolicy]. */
+    const val RETRIGGER_RESTART = 0
+    const val RETRIGGER_QUEUE = 1
+    const val RETRIGGER_IGNORE = 2
+
     private var isLibraryLoaded = false
 
+    // Last frequency passed to initEngine, reused when the engine is
+    // restarted after shutdownEngine.
+    @Volatile
+    private var lastFrequency = 0
+
+    // Last config passed to updateConfig, and the one still waiting for the
+    // engine: the native side rejects config while the engine is not running,
+    // so initEngine re-applies it.
+    @Volatile
+    private var lastConfig: Triple<String, Int, Int>? = null
+    @Volatile
+    private var pendingConfig: Triple<String, Int, Int>? = null
+
+    /** Whether the native side recognises flicks; see [setFlickDetector]. */
+    @Volatile
+    var isNativeFlickEnabled = false
+        private set
+
     // Load the native library on first access to this object, so any native
     // call (including the public wrappers below) is safe even before
     // DataManager's async initEngine runs.
@@ -25,6 +110,8 @@ object Net {
     }
 
     private external fun nativeInit(frequency: Int)
+    private external fun nativeShutdown()
+    @Throws(NetConfigException::class)
     private external fun nativeUpdateConfig(ip: String, port: Int, protocolType: Int)
     private external fun nativeGetState(): Int
     private external fun nativeToggleClient()
@@ -32,28 +119,67 @@ object Net {
     private external fun nativeUpdateFlickCoords(index: Int, y: Int)
     private external fun nativeTouchDown(pid: Int, y: Int)
     private external fun nativeTouchUp(pid: Int)
+    @Throws(NetConfigException::class)
+    private external fun nativeSetPulseProfile(stepUs: Int, order: Int, baseline: Int, sweeps: Int)
+    @Throws(NetConfigException::class)
+    private external fun nativeSetRetriggerPolicy(policy: Int)
+    private external fun nativeSetAirGeometry(height: Float, pairRatio: Float)
+    private external fun nativeSetFlickDetector(minDistance: Float, minVelocity: Float, maxDurationMs: Int, airDown: Boolean)
 
     private external fun nativeTriggerFlick()
+    private external fun nativeTriggerAirDown()
 
     private external fun nativeUpdateState(
         packetType: Int,
         buttonMask: Int,
         airByte: Int,
         sliderMask: Int,
-        handshakePayload: Int,
         cardBcd: ByteArray?,
         airMode: Int
     )
 
     private external fun nativeMickeyButton(enabled: Int)
 
+    private external fun nativeGetTelemetry(): LongArray?
+    private external fun nativeResetTelemetry()
+    private external fun nativeSetFeatures(mask: Int)
+    private external fun nativeGetNegotiated(): Int
+    private external fun nativeReadLeds(out: ByteArray, seen: Long): Long
+    private external fun nativeSetPingInterval(intervalMs: Int)
+    private external fun nativeGetRtt(): LongArray?
+    private external fun nativeSetSendMode(repeats: Int, keepaliveMs: Int)
+    private external fun nativeSetMissPolicy(maxBurst: Int)
+    private external fun nativeSetTimer(spinMarginUs: Int, blockWhenIdle: Boolean)
+    @Throws(NetConfigException::class)
+    private external fun nativeSetDialect(dialect: Int)
+    private external fun nativeDiscover(timeoutMs: Int): Array<String>?
+    private external fun nativeSetPairingKey(key: ByteArray?)
+    private external fun nativeGetAuthStats(): LongArray?
+
     fun initEngine(frequency: Int) {
+        lastFrequency = frequency
         try {
             loadLibrary()
             if (isLibraryLoaded) nativeInit(frequency)
         } catch (e: Exception) {
             Log.e("Net", "Init failed", e)
         }
+        // May resolve a hostname, so never on the caller's (main) thread.
+        pendingConfig?.let { (ip, port, protocolType) ->
+            thread(name = "NetConfig") { updateConfig(ip, port, protocolType) }
+        }
+    }
+
+    /** Restarts the engine after [shutdownEngine], with the last known frequency. */
+    fun resumeEngine() {
+        if (lastFrequency > 0) initEngine(lastFrequency)
+    }
+
+    /** Joins the native threads and releases sockets; config must be re-sent after [resumeEngine]. */
+    fun shutdownEngine() {
+        loadLibrary()
+        if (isLibraryLoaded) nativeShutdown()
+        pendingConfig = lastConfig
     }
 
     /** @return raw engine state: 0 = suspended, 1 = active, 2 = waiting */
@@ -78,14 +204,81 @@ object Net {
         nativeUpdateFlickCoords(index, y)
     }
 
+    /**
+     * Shapes the air pulse of Flick and Auto modes: each swept zone is lit
+     * for [stepMs], in [order] (ZONE_ORDER_*), [sweeps] times per trigger.
+     * [baseline] is a zone mask (bit 0 = zone 1) held throughout; every
+     * other zone is swept. The defaults are the original pulse. Kept across
+     * engine restarts.
+     */
+    fun setPulseProfile(
+        stepMs: Float = 10f,
+        order: Int = ZONE_ORDER_UP,
+        baseline: Int = 0b100000,
+        sweeps: Int = 1
+    ): Result<Unit> {
+        loadLibrary()
+        if (!isLibraryLoaded) return Result.failure(IllegalStateException("rustnithm not loaded"))
+        return try {
+            nativeSetPulseProfile((stepMs * 1000).toInt(), order, baseline, sweeps)
+            Result.success(Unit)
+        } catch (e: NetConfigException) {
+            Log.w("Net", "Pulse profile rejected: ${e.message}")
+            Result.failure(e)
+        }
+    }
+
+    /**
+     * [RETRIGGER_RESTART] (default) starts the sweep over on every flick,
+     * [RETRIGGER_QUEUE] plays one whole sweep per flick back to back, and
+     * [RETRIGGER_IGNORE] drops flicks until the sweep ends. Air-down
+     * triggers follow the same policy. Kept across engine restarts.
+     */
+    fun setRetriggerPolicy(policy: Int): Result<Unit> {
+        loadLibrary()
+        if (!isLibraryLoaded) return Result.failure(IllegalStateException("rustnithm not loaded"))
+        return try {
+            nativeSetRetriggerPolicy(policy)
+            Result.success(Unit)
+        } catch (e: NetConfigException) {
+            Log.w("Net", "Retrigger policy rejected: ${e.message}")
+            Result.failure(e)
+        }
+    }
+
     fun triggerFlick() {
         loadLibrary()
         nativeTriggerFlick()
     }
 
-    fun updateConfig(ip: String, port: Int, protocolType: Int) {
+    /**
+     * Sweeps the air zones from the top down for air-action notes. During a
+     * flick's sweep it waits for that one to end, and vice versa.
+     */
+    fun triggerAirDown() {
         loadLibrary()
-        if (isLibraryLoaded) nativeUpdateConfig(ip, port, protocolType)
+        if (isLibraryLoaded) nativeTriggerAirDown()
+    }
+
+    /**
+     * Applies the target config. Fails with a [NetConfigException] if the native
+     * side rejected it; an [NetConfigException.EngineNotInitialized] failure is
+     * retried automatically once the engine starts.
+     */
+    fun updateConfig(ip: String, port: Int, protocolType: Int): Result<Unit> {
+        val config = Triple(ip, port, protocolType)
+        lastConfig = config
+        pendingConfig = null
+        loadLibrary()
+        if (!isLibraryLoaded) return Result.failure(IllegalStateException("rustnithm not loaded"))
+        return try {
+            nativeUpdateConfig(ip, port, protocolType)
+            Result.success(Unit)
+        } catch (e: NetConfigException) {
+            if (e is NetConfigException.EngineNotInitialized) pendingConfig = config
+            Log.w("Net", "Config rejected: ${e.message}")
+            Result.failure(e)
+        }
     }
 
     fun setMickeyState(enabled: Boolean) {
@@ -93,6 +286,180 @@ object Net {
         if (isLibraryLoaded) nativeMickeyButton(if (enabled) 1 else 0)
     }
 
+    fun getTelemetry(): Telemetry? {
+        loadLibrary()
+        if (!isLibraryLoaded) return null
+        val v = nativeGetTelemetry() ?: return null
+        if (v.size < 9) return null
+        return Telemetry(v[0], v[1], v[2], v[3], v[4], v[5], v[6], v[7], v[8])
+    }
+
+    /** Pings the server every [intervalMs] (0 = off), also while the client is suspended. */
+    fun setPingInterval(intervalMs: Int) {
+        loadLibrary()
+        if (isLibraryLoaded) nativeSetPingInterval(intervalMs)
+    }
+
+    fun getRtt(): Rtt? {
+        loadLibrary()
+        if (!isLibraryLoaded) return null
+        val v = nativeGetRtt() ?: return null
+        if (v.size < 8) return null
+        return Rtt(v[0], v[1], v[2], v[3], v[4], v[5], v[6], v[7])
+    }
+
+    fun resetTelemetry() {
+        loadLibrary()
+        if (isLibraryLoaded) nativeResetTelemetry()
+    }
+
+    /**
+     * Copies the newest server LED frame into [out] (at least [LED_FRAME_SIZE]
+     * bytes) unless it is still the frame with version [seen]. Cheap enough to
+     * call every UI frame. @return the frame version, 0 until the first frame.
+     */
+    fun readLeds(out: ByteArray, seen: Long): Long {
+        loadLibrary()
+        if (!isLibraryLoaded || out.size < LED_FRAME_SIZE) return seen
+        return nativeReadLeds(out, seen)
+    }
+
+    /**
+     * Protocol features offered to the server in the hello exchange, as a mask
+     * of the FEATURE_* constants (all by default). Each is used only if the
+     * server supports it too.
+     */
+    fun setFeatures(mask: Int) {
+        loadLibrary()
+        if (isLibraryLoaded) nativeSetFeatures(mask)
+    }
+
+    /** The server's protocol version and the agreed features, or null until it answered. */
+    fun getNegotiated(): Pair<Int, Int>? {
+        loadLibrary()
+        if (!isLibraryLoaded) return null
+        val raw = nativeGetNegotiated()
+        if (raw < 0) return null
+        return Pair(raw shr 16, raw and 0xFFFF)
+    }
+
+    /**
+     * With [delta] on, input is sent only when it changes, [repeats] more times
+     * for loss resilience, and then every [keepaliveMs] until the next change.
+     * With it off, every tick is sent.
+     */
+    fun setSendMode(delta: Boolean, repeats: Int = 3, keepaliveMs: Int = 100) {
+        loadLibrary()
+        if (isLibraryLoaded) nativeSetSendMode(repeats, if (delta) keepaliveMs.coerceAtLeast(1) else 0)
+    }
+
+    /**
+     * What the send loop does with ticks it woke up too late for: with
+     * [catchUp] on, up to [maxBurst] of them are sent back to back; with it
+     * off, they are skipped and sending resumes on the next tick.
+     */
+    fun setMissPolicy(catchUp: Boolean, maxBurst: Int = 4) {
+        loadLibrary()
+        if (isLibraryLoaded) nativeSetMissPolicy(if (catchUp) maxBurst.coerceAtLeast(1) else 0)
+    }
+
+    /**
+     * How the engine thread waits between ticks: with [spin] on it busy-waits
+     * (lowest jitter, one core at 100%); otherwise it sleeps and spins only the
+     * last [spinMarginUs]. With [blockWhenIdle] it parks while the client is
+     * suspended.
+     */
+    fun setTimer(spin: Boolean = false, spinMarginUs: Int = 50, blockWhenIdle: Boolean = true) {
+        loadLibrary()
+        if (isLibraryLoaded) nativeSetTimer(if (spin) -1 else spinMarginUs.coerceAtLeast(0), blockWhenIdle)
+    }
+
+    /**
+     * Selects the wire format: [DIALECT_RUSTNITHM] (default) or
+     * [DIALECT_BROKENITHM] to drive an original Brokenithm server over the
+     * same UDP/TCP transports. Kept across engine restarts.
+     */
+    fun setDialect(dialect: Int): Result<Unit> {
+        loadLibrary()
+        if (!isLibraryLoaded) return Result.failure(IllegalStateException("rustnithm not loaded"))
+        return try {
+            nativeSetDialect(dialect)
+            Result.success(Unit)
+        } catch (e: NetConfigException) {
+            Log.w("Net", "Dialect rejected: ${e.message}")
+            Result.failure(e)
+        }
+    }
+
+    /**
+     * Broadcasts a discovery probe on the local subnet and returns the servers
+     * that answered within [timeoutMs]. Blocks for the whole timeout, so never
+     * call it on the main thread.
+     */
+    fun discover(timeoutMs: Int = 500): List<DiscoveredServer> {
+        loadLibrary()
+        if (!isLibraryLoaded) return emptyList()
+        val lines = nativeDiscover(timeoutMs) ?: return emptyList()
+        return lines.mapNotNull { line ->
+            val parts = line.split('|', limit = 4)
+            if (parts.size < 4) return@mapNotNull null
+            val port = parts[1].toIntOrNull() ?: return@mapNotNull null
+            val transports = parts[2].toIntOrNull() ?: return@mapNotNull null
+            DiscoveredServer(parts[0], port, transports and 1 != 0, transports and 2 != 0, parts[3])
+        }
+    }
+
+    /**
+     * Enters pairing mode with a key shared with the server (null or empty
+     * leaves it): every frame is then authenticated, and server frames that
+     * are not are dropped. Use a long random key; a short PIN can be guessed
+     * offline from captured frames.
+     */
+    fun setPairingKey(key: ByteArray?) {
+        loadLibrary()
+        if (isLibraryLoaded) nativeSetPairingKey(key)
+    }
+
+    fun getAuthStats(): AuthStats? {
+        loadLibrary()
+        if (!isLibraryLoaded) return null
+        val v = nativeGetAuthStats() ?: return null
+        if (v.size < 2) return null
+        return AuthStats(v[0], v[1])
+    }
+
+    /**
+     * Where the air area is: touch y runs from 0 at its top edge to [height]
+     * at its bottom, and [pairRatio] (multiA) of a zone's height next to each
+     * edge also lights the neighbour. Once set, Native mode builds the air
+     * byte from the onTouch* stream and ignores the one in [sendFullState];
+     * a [height] of 0 hands it back.
+     */
+    fun setAirGeometry(height: Float, pairRatio: Float) {
+        loadLibrary()
+        if (isLibraryLoaded) nativeSetAirGeometry(height, pairRatio)
+    }
+
+    /**
+     * Lets the native side decide what counts as a flick in Flick mode: a
+     * stroke covering [minDistance] px within [maxDurationMs], at
+     * [minVelocity] px/s or faster. Each stroke fires once; the pointer has
+     * to turn around for the next. With [airDown], downward flicks trigger
+     * the air-down sweep (see [triggerAirDown]). Pass null to go back to
+     * [triggerFlick].
+     */
+    fun setFlickDetector(
+        minDistance: Float?,
+        minVelocity: Float = 800f,
+        maxDurationMs: Int = 150,
+        airDown: Boolean = false
+    ) {
+        loadLibrary()
+        if (!isLibraryLoaded) return
+        nativeSetFlickDetector(minDistance ?: 0f, minVelocity, maxDurationMs, airDown)
+        isNativeFlickEnabled = minDistance != null && minDistance > 0f
+    }
+
     fun onTouchDown(pid: Int, y: Float) {
         loadLibrary()
         if (isLibraryLoaded) nativeTouchDown(pid, y.toInt())
@@ -129,33 +496,32 @@ object Net {
                     val low = accessCode[i * 2 + 1].digitToInt(16)
                     bcd[i] = ((high shl 4) or low).toByte()
                 }
-                nativeUpdateState(48, 0, 0, 0, 0, bcd, airMode)
+                nativeUpdateState(48, 0, 0, 0, bcd, airMode)
                 return
             } catch (_: Exception) {
                 Log.e("Net", "Access code format error")
             }
         }
 
-        if (coin || service || test) {
-            var mask = 0
-            if (coin) mask = mask or 0x01
-            if (service) mask = mask or 0x02
-            if (test) mask = mask or 0x04
-            nativeUpdateState(16, mask, 0, 0, 0, null, airMode)
-        } else {
-            var airByte = 0
-            if (airMode == 1) {
-                for (id in air) {
-                    val bitIndex = id - 1
-                    if (bitIndex in 0..5) airByte = airByte or (1 shl bitIndex)
-                }
+        // Every field is filled in whichever packet type wins, so the
+        // full-state packet always sees the complete input.
+        var mask = 0
+        if (coin) mask = mask or 0x01
+        if (service) mask = mask or 0x02
+        if (test) mask = mask or 0x04
+        var airByte = 0
+        if (airMode == 1) {
+            for (id in air) {
+                val bitIndex = id - 1
+                if (bitIndex in 0..5) airByte = airByte or (1 shl bitIndex)
             }
-            var sliderMask = 0
-            for (id in slide) {
-                val adjustedId = id - 1
-                if (adjustedId in 0..31) sliderMask = sliderMask or (1 shl adjustedId)
-            }
-            nativeUpdateState(32, 0, airByte, sliderMask, 0, null, airMode)
         }
+        var sliderMask = 0
+        for (id in slide) {
+            val adjustedId = id - 1
+            if (adjustedId in 0..31) sliderMask = sliderMask or (1 shl adjustedId)
+        }
+        val packetType = if (mask != 0) 16 else 32
+        nativeUpdateState(packetType, mask, airByte, sliderMask, null, airMode)
     }
 }
\ No newline at end of file
@@ -0,0 +1,15 @@
+package org.cf0x.rustnithm.Data
+
+/**
+ * Thrown by the native setters that validate their input: nativeUpdateConfig
+ * when the target cannot be applied, nativeSetDialect (UnsupportedProtocol),
+ * and nativeSetPulseProfile and nativeSetRetriggerPolicy (InvalidPulseProfile).
+ */
+sealed class NetConfigException(message: String) : Exception(message) {
+    class InvalidAddress(message: String) : NetConfigException(message)
+    class UnresolvedHost(message: String) : NetConfigException(message)
+    class BindFailed(message: String) : NetConfigException(message)
+    class UnsupportedProtocol(message: String) : NetConfigException(message)
+    class EngineNotInitialized(message: String) : NetConfigException(message)
+    class InvalidPulseProfile(message: String) : NetConfigException(message)
+}
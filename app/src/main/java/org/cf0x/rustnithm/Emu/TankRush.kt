@@ -2,6 +2,7 @@ package org.cf0x.rustnithm.Emu
 
 import kotlinx.coroutines.*
 import org.cf0x.rustnithm.Data.DataManager
+import org.cf0x.rustnithm.Data.Net
 
 object TankRush {
     private var job: Job? = null
@@ -17,12 +18,15 @@ object TankRush {
                 val minus = dataManager.flickEqualizerMinus.value
                 val flickOnce = dataManager.flickOnce.value
 
-                TankManager.analysisLoop(
-                    threshold = threshold,
-                    plus = plus,
-                    minus = minus,
-                    flickOnce = flickOnce
-                )
+                // The native recognizer fires flicks on its own.
+                if (!Net.isNativeFlickEnabled) {
+                    TankManager.analysisLoop(
+                        threshold = threshold,
+                        plus = plus,
+                        minus = minus,
+                        flickOnce = flickOnce
+                    )
+                }
 
                 delay(10)
             }
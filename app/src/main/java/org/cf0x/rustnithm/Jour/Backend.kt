@@ -16,12 +16,30 @@ object JourBackend {
         }
     }
 
+    /**
+     * Accepts an IPv4 address, an IPv6 literal (optionally in brackets) or a
+     * hostname. Only a syntax check; the native side parses and resolves.
+     */
+    fun validateHost(host: String): Boolean {
+        if (host.isEmpty() || host.length > 253) return false
+        if (host.all { it.isDigit() || it == '.' }) return validateIp(host)
+        val bare = host.removeSurrounding("[", "]")
+        if (bare.contains(':')) {
+            return bare.all { it.isLetterOrDigit() || it == ':' || it == '.' || it == '%' }
+        }
+        return host.removeSuffix(".").split('.').all { label ->
+            label.isNotEmpty() && label.length <= 63 &&
+                !label.startsWith('-') && !label.endsWith('-') &&
+                label.all { it.isLetterOrDigit() || it == '-' }
+        }
+    }
+
     fun validatePort(port: String): Boolean {
         return port.toIntOrNull()?.let { it in 0..65535 } ?: false
     }
 
-    fun updateNetworkConfig(ip: String, port: Int, protocol: Int) {
-        Net.updateConfig(ip, port, protocol)
+    fun updateNetworkConfig(ip: String, port: Int, protocol: Int): Result<Unit> {
+        return Net.updateConfig(ip, port, protocol)
     }
 
     fun sendGameState(
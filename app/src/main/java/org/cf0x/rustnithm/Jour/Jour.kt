@@ -12,6 +12,8 @@ import androidx.compose.ui.platform.LocalContext
 import androidx.compose.ui.platform.LocalFocusManager
 import androidx.compose.ui.platform.LocalView
 import androidx.lifecycle.viewmodel.compose.viewModel
+import kotlinx.coroutines.Dispatchers
+import kotlinx.coroutines.withContext
 import org.cf0x.rustnithm.Data.DataManager
 import org.cf0x.rustnithm.Data.Haptic
 import org.cf0x.rustnithm.Data.Net
@@ -60,6 +62,7 @@ fun Jour(
 
     DisposableEffect(Unit) {
         haptic.attachView(view)
+        Net.resumeEngine()
         onDispose {
             TankRush.stop()
             // Read the real native state instead of the connState parameter:
@@ -70,6 +73,8 @@ fun Jour(
             if (raw == 1 || raw == 2) {
                 JourBackend.toggleConnection()
             }
+            // Release the engine thread and sockets while off the play screen.
+            Net.shutdownEngine()
         }
     }
 
@@ -84,8 +89,11 @@ fun Jour(
     LaunchedEffect(savedIp, savedPort, protocolType) {
         if (savedIp.isNotEmpty() && savedPort.isNotEmpty()) {
             val port = savedPort.toIntOrNull() ?: 0
-            if (JourBackend.validateIp(savedIp) && JourBackend.validatePort(savedPort)) {
-                JourBackend.updateNetworkConfig(savedIp, port, protocolType)
+            if (JourBackend.validateHost(savedIp) && JourBackend.validatePort(savedPort)) {
+                // Hostnames are resolved inside the native call; keep it off the main thread.
+                withContext(Dispatchers.IO) {
+                    JourBackend.updateNetworkConfig(savedIp, port, protocolType)
+                }
             }
         }
     }
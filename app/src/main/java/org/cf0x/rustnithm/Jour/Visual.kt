@@ -70,6 +70,12 @@ fun JourVisual(
         lastSlide = activatedSlide
     }
 
+    LaunchedEffect(containerSize, percentPage, multiA) {
+        if (containerSize.height > 0) {
+            org.cf0x.rustnithm.Data.Net.setAirGeometry(containerSize.height * percentPage, multiA)
+        }
+    }
+
     Box(
         modifier = Modifier
             .fillMaxSize()
@@ -90,18 +96,22 @@ fun JourVisual(
                     awaitEachGesture {
                         while (true) {
                             val event = awaitPointerEvent()
-                            if (airMode == 2) {
-                                event.changes.forEach { change ->
-                                    val pId = change.id.hashCode()
-                                    if (change.pressed) {
-                                        var poolIdx = pointerMapping.indexOf(pId)
-                                        if (poolIdx == -1) {
-                                            poolIdx = pointerMapping.indexOf(-1)
-                                            if (poolIdx != -1) pointerMapping[poolIdx] = pId
-                                        }
+                            event.changes.forEach { change ->
+                                val pId = change.id.hashCode()
+                                if (change.pressed) {
+                                    var poolIdx = pointerMapping.indexOf(pId)
+                                    if (poolIdx == -1) {
+                                        poolIdx = pointerMapping.indexOf(-1)
                                         if (poolIdx != -1) {
-                                            val yCoord = change.position.y.toInt()
-                                            org.cf0x.rustnithm.Data.Net.updateFlickCoords(poolIdx, yCoord)
+                                            pointerMapping[poolIdx] = pId
+                                            org.cf0x.rustnithm.Data.Net.onTouchDown(poolIdx, change.position.y)
+                                        }
+                                    } else {
+                                        org.cf0x.rustnithm.Data.Net.onTouchMove(poolIdx, change.position.y)
+                                    }
+                                    if (poolIdx != -1) {
+                                        val yCoord = change.position.y.toInt()
+                                        if (airMode == 2) {
                                             org.cf0x.rustnithm.Emu.TankManager.updateFlick(
                                                 index = poolIdx,
                                                 y = yCoord,
@@ -111,11 +121,13 @@ fun JourVisual(
                                                 containerHeight = containerSize.height
                                             )
                                         }
-                                    } else {
-                                        val poolIdx = pointerMapping.indexOf(pId)
-                                        if (poolIdx != -1) {
-                                            pointerMapping[poolIdx] = -1
-                                            org.cf0x.rustnithm.Data.Net.updateFlickCoords(poolIdx, -1)
+                                    }
+                                } else {
+                                    val poolIdx = pointerMapping.indexOf(pId)
+                                    if (poolIdx != -1) {
+                                        pointerMapping[poolIdx] = -1
+                                        org.cf0x.rustnithm.Data.Net.onTouchUp(poolIdx)
+                                        if (airMode == 2) {
                                             org.cf0x.rustnithm.Emu.TankManager.updateFlick(
                                                 index = poolIdx,
                                                 y = -1,
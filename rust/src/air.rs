@@ -1,33 +1,332 @@
+//! Air input: the pulse for Flick and Auto modes, native pointer tracking
+//! for Native mode, and the flick recognizer that can drive Flick mode.
+//!
+//! The air area is split into six stacked zones, zone 1 (bit 0) at the
+//! bottom. A pointer lights the zone it is in and, within `pair_ratio` of a
+//! zone's height from its edge, the neighbouring zone as well, like a hand
+//! breaking two IR beams at once.
+
+use std::collections::VecDeque;
 use std::sync::atomic::Ordering;
-use std::sync::Mutex;
-use once_cell::sync::Lazy;
-use crate::{DATA_POOL, pulse::PulseState};
+use crate::engine::Shared;
+use crate::pulse::MAX_WAITING;
+use crate::timer;
+
+pub const AIR_ZONES: usize = 6;
+/// Pointers tracked at once; further ones are ignored until one lifts.
+pub const MAX_POINTERS: usize = 10;
+
+/// Where the air area is, in the coordinates of the touch stream: `y` grows
+/// downwards from the top edge of the air area to `height` at its bottom.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AirGeometry {
+    pub height: f32,
+    /// Share of a zone's height next to each edge that also lights the
+    /// neighbour; clamped to 0..=0.5.
+    pub pair_ratio: f32,
+}
+
+impl AirGeometry {
+    /// The zones one pointer at `y` lights, as air bits.
+    pub fn zones_at(&self, y: f32) -> u8 {
+        if !(0.0..=self.height).contains(&y) {
+            return 0;
+        }
+        let zone_height = self.height / AIR_ZONES as f32;
+        let pair = zone_height * self.pair_ratio.clamp(0.0, 0.5);
+        let from_bottom = self.height - y;
+        let zone = ((from_bottom / zone_height) as usize).min(AIR_ZONES - 1);
+        let start = zone as f32 * zone_height;
+        let mut bits = 1 << zone;
+        if zone > 0 && from_bottom < start + pair {
+            bits |= 1 << (zone - 1);
+        }
+        if zone < AIR_ZONES - 1 && from_bottom > start + zone_height - pair {
+            bits |= 1 << (zone + 1);
+        }
+        bits
+    }
+}
+
+/// Pointers currently down, by the id the UI gave them. Owned behind
+/// `Shared`; builds the Native mode air byte once a geometry is set.
+#[derive(Debug, Clone, Default)]
+pub struct AirTracker {
+    geometry: Option<AirGeometry>,
+    pointers: [Option<(i32, f32)>; MAX_POINTERS],
+}
+
+impl AirTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `None` (or a height that is not positive) turns native tracking off.
+    pub fn set_geometry(&mut self, geometry: Option<AirGeometry>) {
+        self.geometry = geometry.filter(|g| g.height > 0.0);
+    }
+
+    pub fn geometry(&self) -> Option<AirGeometry> {
+        self.geometry
+    }
+
+    /// Whether the air byte comes from here rather than from the UI.
+    pub fn is_active(&self) -> bool {
+        self.geometry.is_some()
+    }
+
+    pub fn down(&mut self, pid: i32, y: f32) {
+        self.move_to(pid, y);
+    }
+
+    /// Moves `pid`, starting to track it if it is new: a move can arrive
+    /// before (or instead of) its down.
+    pub fn move_to(&mut self, pid: i32, y: f32) {
+        if let Some(slot) = self.pointers.iter_mut().flatten().find(|(id, _)| *id == pid) {
+            slot.1 = y;
+        } else if let Some(slot) = self.pointers.iter_mut().find(|p| p.is_none()) {
+            *slot = Some((pid, y));
+        }
+    }
+
+    pub fn up(&mut self, pid: i32) {
+        for slot in &mut self.pointers {
+            if matches!(slot, Some((id, _)) if *id == pid) {
+                *slot = None;
+            }
+        }
+    }
+
+    /// Lifts every pointer, keeping the geometry.
+    pub fn clear(&mut self) {
+        self.pointers = [None; MAX_POINTERS];
+    }
+
+    pub fn pointers(&self) -> usize {
+        self.pointers.iter().flatten().count()
+    }
+
+    /// The union of every pointer's zones; 0 without a geometry.
+    pub fn air_byte(&self) -> u8 {
+        let Some(geometry) = self.geometry else { return 0 };
+        self.pointers.iter().flatten().fold(0, |air, &(_, y)| air | geometry.zones_at(y))
+    }
+}
+
+/// When a stroke counts as a flick: it must cover `min_distance` within
+/// `max_duration_ns`, at `min_velocity` or faster over that stretch.
+/// Distances are in touch coordinates, velocity in units per second.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FlickConfig {
+    pub min_distance: f32,
+    pub min_velocity: f32,
+    pub max_duration_ns: u64,
+    /// Downward flicks trigger the air-down sweep instead of a plain one.
+    pub air_down: bool,
+}
+
+impl Default for FlickConfig {
+    fn default() -> Self {
+        Self { min_distance: 48.0, min_velocity: 800.0, max_duration_ns: 150_000_000, air_down: false }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlickDirection {
+    /// Towards smaller `y`, i.e. up the screen.
+    Up,
+    Down,
+}
 
-static PULSE_CONTROLLER: Lazy<Mutex<PulseState>> = Lazy::new(|| Mutex::new(PulseState::new()));
+/// Samples kept per stroke; older ones drop out early on very high touch
+/// rates, which only makes the window shorter.
+const STROKE_SAMPLES: usize = 32;
 
-pub fn process_flick_sampling() {
-    let air_mode = DATA_POOL.air_mode.load(Ordering::Relaxed);
-    let mickey_on = DATA_POOL.mickey.load(Ordering::Relaxed) == 1;
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    y: f32,
+    t_ns: u64,
+}
+
+/// One pointer's movement since it last changed direction.
+#[derive(Debug, Clone)]
+struct Stroke {
+    pid: i32,
+    direction: Option<FlickDirection>,
+    /// One flick per stroke; the pointer has to turn around for the next.
+    fired: bool,
+    samples: VecDeque<Sample>,
+}
+
+/// Recognises flicks in the per-pointer move stream. Each stroke, a run of
+/// movement in one direction, fires at most once. Pure, with the caller
+/// passing the clock, so recorded traces replay exactly.
+#[derive(Debug, Clone, Default)]
+pub struct FlickDetector {
+    config: Option<FlickConfig>,
+    strokes: Vec<Stroke>,
+}
+
+impl FlickDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `None` turns recognition off; flicks then only come from
+    /// `Engine::trigger_flick`.
+    pub fn set_config(&mut self, config: Option<FlickConfig>) {
+        self.config = config;
+        self.strokes.clear();
+    }
+
+    pub fn config(&self) -> Option<FlickConfig> {
+        self.config
+    }
+
+    pub fn down(&mut self, pid: i32, y: f32, now_ns: u64) {
+        self.up(pid);
+        if self.config.is_none() || self.strokes.len() >= MAX_POINTERS {
+            return;
+        }
+        let mut samples = VecDeque::with_capacity(STROKE_SAMPLES);
+        samples.push_back(Sample { y, t_ns: now_ns });
+        self.strokes.push(Stroke { pid, direction: None, fired: false, samples });
+    }
+
+    /// Feeds one move; returns the flick it completes, if any. A move for
+    /// an unknown pointer starts it as if it had just gone down there.
+    pub fn on_move(&mut self, pid: i32, y: f32, now_ns: u64) -> Option<FlickDirection> {
+        let config = self.config?;
+        let Some(stroke) = self.strokes.iter_mut().find(|s| s.pid == pid) else {
+            self.down(pid, y, now_ns);
+            return None;
+        };
+        let last = *stroke.samples.back()?;
+        let direction = if y < last.y {
+            Some(FlickDirection::Up)
+        } else if y > last.y {
+            Some(FlickDirection::Down)
+        } else {
+            stroke.direction
+        };
+        if direction != stroke.direction {
+            // Turned around: the new stroke starts at the turning point.
+            stroke.samples.clear();
+            stroke.samples.push_back(last);
+            stroke.direction = direction;
+            stroke.fired = false;
+        }
+        if stroke.samples.len() == STROKE_SAMPLES {
+            stroke.samples.pop_front();
+        }
+        stroke.samples.push_back(Sample { y, t_ns: now_ns });
+        while stroke.samples.front().is_some_and(|s| now_ns.saturating_sub(s.t_ns) > config.max_duration_ns) {
+            stroke.samples.pop_front();
+        }
+
+        if stroke.fired {
+            return None;
+        }
+        // Any stretch ending now will do, so a rest before the flick does
+        // not slow it down on average.
+        let flicked = stroke.samples.iter().any(|s| {
+            let distance = (y - s.y).abs();
+            let elapsed_s = now_ns.saturating_sub(s.t_ns) as f32 / 1e9;
+            distance >= config.min_distance && distance >= config.min_velocity * elapsed_s
+        });
+        stroke.fired = flicked;
+        stroke.direction.filter(|_| flicked)
+    }
+
+    pub fn up(&mut self, pid: i32) {
+        self.strokes.retain(|s| s.pid != pid);
+    }
+
+    /// Lifts every pointer, keeping the config.
+    pub fn clear(&mut self) {
+        self.strokes.clear();
+    }
+}
+
+pub(crate) fn process_flick_sampling(shared: &Shared) {
+    let data = &shared.data;
+    let air_mode = data.air_mode.load(Ordering::Relaxed);
+    let mickey_on = data.mickey.load(Ordering::Relaxed) == 1;
+    let flicks = data.pending_flicks.swap(0, Ordering::SeqCst);
+    let air_downs = data.pending_air_downs.swap(0, Ordering::SeqCst);
+    // Triggers while the pulse is not running are dropped, not saved up.
     if air_mode == 1 || !mickey_on {
         return;
     }
 
-    if let Ok(mut pulse) = PULSE_CONTROLLER.lock() {
+    if let Ok(mut pulse) = shared.pulse.lock() {
+        let now = timer::monotonic_ns();
         if air_mode == 2 {
-            if DATA_POOL.flick_signal.swap(0, Ordering::SeqCst) == 1 {
-                pulse.trigger();
+            // Within one sample the order is lost; flicks go first.
+            // More than fit the queue cannot change the outcome.
+            let cap = MAX_WAITING as u32 + 1;
+            for _ in 0..flicks.min(cap) {
+                pulse.trigger(now);
+            }
+            for _ in 0..air_downs.min(cap) {
+                pulse.trigger_air_down(now);
             }
         }
 
         let is_auto = air_mode == 3;
-        if is_auto && pulse.start_time.is_none() {
-            pulse.trigger();
+        if is_auto && !pulse.is_active() {
+            pulse.trigger(now);
         }
-        let bit_result = pulse.get_air_byte(is_auto);
-        DATA_POOL.air_byte.store(bit_result as u32, Ordering::Relaxed);
+        let bit_result = pulse.get_air_byte(now, is_auto);
+        data.set_air(bit_result);
     }
 }
 
-pub fn update_touch_down(_pid: i32, _y: f32) {}
-pub fn update_touch_move(_pid: i32, _y: f32) {}
-pub fn update_touch_up(_pid: i32) {}
\ No newline at end of file
+/// Runs `update` on the tracker and, in Native mode with a geometry set,
+/// publishes the new air byte while still holding the lock, so a concurrent
+/// `Engine::update_state` cannot put an older one back.
+fn track(shared: &Shared, update: impl FnOnce(&mut AirTracker)) {
+    if let Ok(mut tracker) = shared.air.lock() {
+        update(&mut tracker);
+        if tracker.is_active() && shared.data.air_mode.load(Ordering::Relaxed) == 1 {
+            shared.data.set_air(tracker.air_byte());
+        }
+    }
+}
+
+/// A recognised flick drives the pulse like `Engine::trigger_flick` (or
+/// `trigger_air_down`), but only in Flick mode.
+fn on_flick(shared: &Shared, flick: Option<FlickDirection>, air_down: bool) {
+    let Some(direction) = flick else { return };
+    if shared.data.air_mode.load(Ordering::Relaxed) != 2 {
+        return;
+    }
+    let pending = match direction {
+        FlickDirection::Down if air_down => &shared.data.pending_air_downs,
+        _ => &shared.data.pending_flicks,
+    };
+    pending.fetch_add(1, Ordering::SeqCst);
+}
+
+pub(crate) fn update_touch_down(shared: &Shared, pid: i32, y: f32) {
+    track(shared, |tracker| tracker.down(pid, y));
+    if let Ok(mut detector) = shared.flick.lock() {
+        detector.down(pid, y, timer::monotonic_ns());
+    }
+}
+
+pub(crate) fn update_touch_move(shared: &Shared, pid: i32, y: f32) {
+    track(shared, |tracker| tracker.move_to(pid, y));
+    let Ok(mut detector) = shared.flick.lock() else { return };
+    let flick = detector.on_move(pid, y, timer::monotonic_ns());
+    let air_down = detector.config().is_some_and(|c| c.air_down);
+    drop(detector);
+    on_flick(shared, flick, air_down);
+}
+
+pub(crate) fn update_touch_up(shared: &Shared, pid: i32) {
+    track(shared, |tracker| tracker.up(pid));
+    if let Ok(mut detector) = shared.flick.lock() {
+        detector.up(pid);
+    }
+}
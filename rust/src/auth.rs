@@ -0,0 +1,313 @@
+//! Optional pairing: frames authenticated with a shared key.
+//!
+//! A sealed frame is the plain frame with header bit 0 set, followed by a
+//! u64 LE counter and the first `TAG_LEN` bytes of
+//! HMAC-SHA256(key, direction || nonce || frame || counter). The direction
+//! byte stops a frame from being reflected back at its sender; the counter,
+//! checked against a sliding window, stops replays within a session.
+//!
+//! The nonce stops them across sessions. Each side draws a fresh one when
+//! its session starts (new key, engine restart, new handshake) and announces
+//! it in the hello or hello ack; the other side seals everything for it with
+//! that nonce, so frames recorded in an earlier session no longer open. Only
+//! the hello goes out before the server's nonce is known, sealed with 0.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use hmac_sha256::HMAC;
+use thiserror::Error;
+use crate::protocol::{self, ClientPacket, MAX_SERVER_PACKET};
+
+pub const TAG_LEN: usize = 8;
+/// Bytes a sealed frame adds: counter + tag.
+pub const AUTH_LEN: usize = 8 + TAG_LEN;
+/// Longest sealed frame in either direction.
+pub const MAX_SEALED: usize = MAX_SERVER_PACKET + AUTH_LEN;
+/// Header bit 0: the frame is sealed. Free in both directions.
+const AUTH_BIT: u8 = 0x01;
+/// Counters this far behind the newest one are rejected outright.
+const REPLAY_WINDOW: u64 = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    ToServer,
+    ToClient,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum AuthError {
+    #[error("frame is not sealed")]
+    Unsealed,
+    #[error("sealed frame too short or too long")]
+    Length,
+    #[error("authentication tag mismatch")]
+    BadTag,
+    #[error("counter {0} already seen or too old")]
+    Replayed(u64),
+}
+
+/// A frame in a fixed buffer, sealed or opened.
+#[derive(Debug, Clone, Copy)]
+pub struct AuthFrame {
+    buf: [u8; MAX_SEALED],
+    len: usize,
+}
+
+impl AuthFrame {
+    fn copy_of(frame: &[u8]) -> Self {
+        let mut buf = [0u8; MAX_SEALED];
+        let len = frame.len().min(MAX_SEALED);
+        buf[..len].copy_from_slice(&frame[..len]);
+        Self { buf, len }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+fn tag(key: &[u8], direction: Direction, nonce: u64, frame: &[u8], counter: u64) -> [u8; TAG_LEN] {
+    let mut mac = HMAC::new(key);
+    mac.update([direction as u8]);
+    mac.update(nonce.to_le_bytes());
+    mac.update(frame);
+    mac.update(counter.to_le_bytes());
+    let full = mac.finalize();
+    let mut out = [0u8; TAG_LEN];
+    out.copy_from_slice(&full[..TAG_LEN]);
+    out
+}
+
+/// Seals a plain frame of at most `MAX_SERVER_PACKET` bytes for the
+/// receiver's session `nonce`.
+pub fn seal(key: &[u8], direction: Direction, nonce: u64, counter: u64, frame: &[u8]) -> AuthFrame {
+    let mut out = AuthFrame::copy_of(frame);
+    let len = frame.len().min(MAX_SERVER_PACKET);
+    if len == 0 {
+        return out;
+    }
+    out.buf[0] |= AUTH_BIT;
+    out.buf[len..len + 8].copy_from_slice(&counter.to_le_bytes());
+    let tag = tag(key, direction, nonce, &out.buf[..len], counter);
+    out.buf[len + 8..len + AUTH_LEN].copy_from_slice(&tag);
+    out.len = len + AUTH_LEN;
+    out
+}
+
+/// Checks the tag against our session `nonce` and returns the plain frame
+/// and its counter. Replays are left to a `ReplayWindow`, which must only
+/// see authentic counters.
+pub fn open(key: &[u8], direction: Direction, nonce: u64, frame: &[u8]) -> Result<(AuthFrame, u64), AuthError> {
+    let header = *frame.first().ok_or(AuthError::Length)?;
+    if header & AUTH_BIT == 0 {
+        return Err(AuthError::Unsealed);
+    }
+    if frame.len() <= AUTH_LEN || frame.len() > MAX_SEALED {
+        return Err(AuthError::Length);
+    }
+    let len = frame.len() - AUTH_LEN;
+    let (body, trailer) = frame.split_at(len);
+    let counter = u64::from_le_bytes(trailer[..8].try_into().expect("8 bytes"));
+    let expected = tag(key, direction, nonce, body, counter);
+    // Constant time, so the tag cannot be guessed byte by byte.
+    let diff = expected.iter().zip(&trailer[8..]).fold(0u8, |acc, (a, b)| acc | (a ^ b));
+    if diff != 0 {
+        return Err(AuthError::BadTag);
+    }
+    let mut plain = AuthFrame::copy_of(body);
+    plain.buf[0] &= !AUTH_BIT;
+    Ok((plain, counter))
+}
+
+/// Accepts each counter once, tolerating `REPLAY_WINDOW` of reordering.
+#[derive(Debug, Clone, Default)]
+pub struct ReplayWindow {
+    highest: u64,
+    /// Bit n set = `highest - n` was seen.
+    seen: u64,
+}
+
+impl ReplayWindow {
+    pub fn accept(&mut self, counter: u64) -> bool {
+        if counter > self.highest {
+            let shift = counter - self.highest;
+            self.seen = if shift >= REPLAY_WINDOW { 0 } else { self.seen << shift };
+            self.seen |= 1;
+            self.highest = counter;
+            return true;
+        }
+        let offset = self.highest - counter;
+        if offset >= REPLAY_WINDOW || self.seen & (1 << offset) != 0 {
+            return false;
+        }
+        self.seen |= 1 << offset;
+        true
+    }
+}
+
+/// Frames dropped by pairing, by reason.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AuthStats {
+    /// Unsealed, malformed or with a wrong tag.
+    pub unauthenticated: u64,
+    pub replayed: u64,
+}
+
+impl AuthStats {
+    /// Field order of the `long[]` handed to Kotlin.
+    pub fn to_array(&self) -> [i64; 2] {
+        [self.unauthenticated as i64, self.replayed as i64]
+    }
+}
+
+/// A fresh session nonce, never 0. The std hasher's keys are seeded from
+/// the OS once per process and step on every `RandomState`, and the clock
+/// is mixed in, so a nonce repeating, even across restarts, is a 64-bit
+/// coincidence.
+fn new_nonce() -> u64 {
+    let now_ns = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(now_ns);
+    hasher.finish() | 1
+}
+
+/// One side of a paired link: seals what it sends, opens what it receives.
+pub(crate) struct Pairing {
+    /// Direction of frames this side sends.
+    outgoing: Direction,
+    key: RwLock<Option<Vec<u8>>>,
+    next_counter: AtomicU64,
+    /// Our session: frames for us must be sealed with it.
+    nonce: AtomicU64,
+    /// The other side's session, from its hello or hello ack; 0 until then.
+    peer_nonce: AtomicU64,
+    window: Mutex<ReplayWindow>,
+    unauthenticated: AtomicU64,
+    replayed: AtomicU64,
+}
+
+impl Pairing {
+    pub fn new(outgoing: Direction) -> Self {
+        Self {
+            outgoing,
+            key: RwLock::new(None),
+            next_counter: AtomicU64::new(0),
+            nonce: AtomicU64::new(new_nonce()),
+            peer_nonce: AtomicU64::new(0),
+            window: Mutex::new(ReplayWindow::default()),
+            unauthenticated: AtomicU64::new(0),
+            replayed: AtomicU64::new(0),
+        }
+    }
+
+    /// `None` unpairs; frames go out plain and plain frames are accepted.
+    /// A different key starts a new session; setting the key already in use
+    /// keeps the current one. Returns whether the key changed.
+    pub fn set_key(&self, key: Option<Vec<u8>>) -> bool {
+        let now_us = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0);
+        self.next_counter.fetch_max(now_us, Ordering::Relaxed);
+        let key = key.filter(|k| !k.is_empty());
+        let Ok(mut guard) = self.key.write() else { return false };
+        if *guard == key {
+            return false;
+        }
+        *guard = key;
+        self.new_session();
+        true
+    }
+
+    /// Draws a new nonce and forgets the peer's, so frames sealed for an
+    /// earlier session are refused. The window starts over with it, as no
+    /// frame it saw can open again.
+    pub fn new_session(&self) {
+        self.nonce.store(new_nonce(), Ordering::Relaxed);
+        self.peer_nonce.store(0, Ordering::Relaxed);
+        if let Ok(mut window) = self.window.lock() {
+            *window = ReplayWindow::default();
+        }
+    }
+
+    /// Our session nonce, to announce in the hello or hello ack.
+    pub fn nonce(&self) -> u64 {
+        self.nonce.load(Ordering::Relaxed)
+    }
+
+    /// The nonce the other side announced; everything sent from now on is
+    /// sealed for it.
+    pub fn set_peer_nonce(&self, nonce: u64) {
+        self.peer_nonce.store(nonce, Ordering::Relaxed);
+    }
+
+    pub fn is_paired(&self) -> bool {
+        self.key.read().map(|k| k.is_some()).unwrap_or(false)
+    }
+
+    /// Whether `packet` may go out yet: while paired, only the hello is sent
+    /// before the peer's nonce is known, as it would refuse anything else.
+    pub fn may_send(&self, packet: &ClientPacket) -> bool {
+        matches!(packet, ClientPacket::Hello { .. })
+            || self.peer_nonce.load(Ordering::Relaxed) != 0
+            || !self.is_paired()
+    }
+
+    /// The frame as it goes on the wire: sealed while paired, else as is.
+    pub fn protect(&self, frame: &[u8]) -> AuthFrame {
+        match self.key.read().ok().as_deref() {
+            Some(Some(key)) => {
+                let counter = self.next_counter.fetch_add(1, Ordering::Relaxed);
+                seal(key, self.outgoing, self.peer_nonce.load(Ordering::Relaxed), counter, frame)
+            }
+            _ => AuthFrame::copy_of(frame),
+        }
+    }
+
+    /// The plain frame, or `None` (counted) if it fails authentication. A
+    /// hello sealed before its sender knew our nonce opens with 0 instead.
+    pub fn accept(&self, frame: &[u8]) -> Option<AuthFrame> {
+        let guard = self.key.read().ok()?;
+        let Some(key) = guard.as_deref() else {
+            return Some(AuthFrame::copy_of(frame));
+        };
+        let incoming = match self.outgoing {
+            Direction::ToServer => Direction::ToClient,
+            Direction::ToClient => Direction::ToServer,
+        };
+        let opened = open(key, incoming, self.nonce(), frame).or_else(|err| match frame.first() {
+            // Only a hello is sealed before its sender knows our nonce.
+            Some(&header)
+                if err == AuthError::BadTag && incoming == Direction::ToServer && protocol::is_handshake(header) =>
+            {
+                open(key, incoming, 0, frame)
+            }
+            _ => Err(err),
+        });
+        match opened {
+            Ok((plain, counter)) => {
+                let fresh = self.window.lock().map(|mut w| w.accept(counter)).unwrap_or(false);
+                if fresh {
+                    Some(plain)
+                } else {
+                    self.replayed.fetch_add(1, Ordering::Relaxed);
+                    None
+                }
+            }
+            Err(_) => {
+                self.unauthenticated.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    pub fn stats(&self) -> AuthStats {
+        AuthStats {
+            unauthenticated: self.unauthenticated.load(Ordering::Relaxed),
+            replayed: self.replayed.load(Ordering::Relaxed),
+        }
+    }
+}
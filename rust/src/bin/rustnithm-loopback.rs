@@ -0,0 +1,80 @@
+//! Local stand-in for Rustnithm-Server.
+//!
+//! Usage: rustnithm-loopback [bind-addr] [--brokenithm]   (default 0.0.0.0:8200)
+//!
+//! Point the client at this machine with either protocol and watch the live
+//! input view; sync requests are confirmed the way the real server does.
+//! Discovery probes on the same address are answered too. With
+//! `--brokenithm` it speaks the original Brokenithm packets instead.
+
+use std::io::Write;
+use std::net::SocketAddr;
+use std::thread;
+use std::time::Duration;
+use rustnithm::discovery::{Announce, Responder, Transports, DISCOVERY_PORT};
+use rustnithm::loopback::LoopbackServer;
+use rustnithm::protocol::PROTOCOL_VERSION;
+use rustnithm::Dialect;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let brokenithm = args.iter().any(|a| a == "--brokenithm");
+    let arg = args.iter().find(|a| !a.starts_with("--")).cloned().unwrap_or_else(|| "0.0.0.0:8200".into());
+    let addr: SocketAddr = match arg.parse() {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("invalid bind address {arg:?}: {e}");
+            std::process::exit(2);
+        }
+    };
+    let server = match LoopbackServer::bind(addr) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("failed to bind {addr}: {e}");
+            std::process::exit(1);
+        }
+    };
+    if brokenithm {
+        server.set_dialect(Dialect::Brokenithm);
+    }
+    println!("listening on {} (udp + tcp{})", server.local_addr(), if brokenithm { ", brokenithm" } else { "" });
+    let announce = Announce {
+        name: "rustnithm-loopback".into(),
+        port: server.local_addr().port(),
+        transports: Transports::BOTH,
+        version: PROTOCOL_VERSION,
+    };
+    // Kept alive for the whole run; discovery is optional.
+    let _responder = match Responder::bind(SocketAddr::new(addr.ip(), DISCOVERY_PORT), announce) {
+        Ok(r) => Some(r),
+        Err(e) => {
+            eprintln!("discovery disabled, failed to bind port {DISCOVERY_PORT}: {e}");
+            None
+        }
+    };
+
+    loop {
+        let v = server.snapshot();
+        let air: String = (0..6).map(|i| if v.air >> i & 1 == 1 { '#' } else { '.' }).collect();
+        let slider: String = (0..32).map(|i| if v.slider >> i & 1 == 1 { '#' } else { '.' }).collect();
+        let card = v.card
+            .map(|bcd| bcd.iter().map(|b| format!("{b:02x}")).collect::<String>())
+            .unwrap_or_else(|| "-".into());
+        print!(
+            "\r{} {:?} btn={:03b} air=[{}] slider=[{}] card={} pkts={} bad={} lost={} reord={} rej={}  ",
+            if v.connected { "ON " } else { "OFF" },
+            v.last_transport,
+            v.buttons,
+            air,
+            slider,
+            card,
+            v.packets,
+            v.bad_frames,
+            v.lost,
+            v.reordered,
+            v.rejected,
+        );
+        let _ = std::io::stdout().flush();
+        thread::sleep(Duration::from_millis(50));
+    }
+}
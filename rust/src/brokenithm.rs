@@ -0,0 +1,258 @@
+//! The original Brokenithm wire format, for servers that speak only that.
+//!
+//! Every frame is `size type[3] body`, where `size` counts the bytes after
+//! itself, so frames delimit themselves on TCP without a length prefix.
+//!
+//! Client -> server:
+//! - `INP`: air[6], slider[32], test, service; 0 = released
+//! - `FNC`: function button, 1 = coin (sent once per press)
+//! - `CRD`: read flag, card type (0 = Aime), access code BCD[10]
+//! - `PIN`: u64 LE, echoed back in `PON`
+//!
+//! Server -> client:
+//! - `LED`: 32 slider BRG triples, right to left as chuniio hands them out;
+//!   the last one is unused and there are no air LEDs
+//! - `PON`: the ping's u64
+//!
+//! There is no sync, hello, stamp or ack: the client confirms its own sync
+//! requests and only LED and ping are available.
+
+use crate::protocol::{ClientPacket, LedFrame, ProtocolError, ServerPacket, AIR_LEDS, SLIDER_LEDS};
+
+const INPUT_TYPE: [u8; 3] = *b"INP";
+const FUNCTION_TYPE: [u8; 3] = *b"FNC";
+const CARD_TYPE: [u8; 3] = *b"CRD";
+const PING_TYPE: [u8; 3] = *b"PIN";
+const PONG_TYPE: [u8; 3] = *b"PON";
+const LED_TYPE: [u8; 3] = *b"LED";
+
+const INPUT_LEN: usize = 4 + AIR_LEDS + 32 + 2;
+const FUNCTION_LEN: usize = 5;
+const CARD_LEN: usize = 4 + 2 + 10;
+const PING_LEN: usize = 4 + 8;
+const LED_CELLS: usize = 32;
+const LED_LEN: usize = 4 + 3 * LED_CELLS;
+/// Longest client frame (input).
+pub const MAX_CLIENT_FRAME: usize = INPUT_LEN;
+
+pub const FUNCTION_COIN: u8 = 1;
+const CARD_AIME: u8 = 0;
+/// Slider cell value for a touched key.
+const SLIDER_TOUCHED: u8 = 0x80;
+
+/// A decoded client frame, for the loopback server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientFrame {
+    Input { air: u8, slider: u32, test: bool, service: bool },
+    /// A function button; `FUNCTION_COIN` is the only one sent.
+    Function(u8),
+    /// `None` once the card was taken away.
+    Card(Option<[u8; 10]>),
+    Ping(u64),
+}
+
+/// Up to two frames produced for one packet.
+#[derive(Debug, Clone, Copy)]
+pub struct Frames {
+    bufs: [[u8; MAX_CLIENT_FRAME]; 2],
+    lens: [usize; 2],
+    count: usize,
+}
+
+impl Frames {
+    fn new() -> Self {
+        Self { bufs: [[0; MAX_CLIENT_FRAME]; 2], lens: [0; 2], count: 0 }
+    }
+
+    fn push(&mut self, kind: [u8; 3], body: &[u8]) {
+        let buf = &mut self.bufs[self.count];
+        let len = 4 + body.len();
+        buf[0] = (len - 1) as u8;
+        buf[1..4].copy_from_slice(&kind);
+        buf[4..len].copy_from_slice(body);
+        self.lens[self.count] = len;
+        self.count += 1;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> {
+        self.bufs.iter().zip(self.lens).take(self.count).map(|(buf, len)| &buf[..len])
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+/// The ping id and send time packed into Brokenithm's u64.
+fn ping_value(id: u16, micros: u32) -> u64 {
+    (u64::from(id) << 32) | u64::from(micros)
+}
+
+/// Client-side encoder. Brokenithm signals coin and card as edges, so it
+/// remembers what was sent last; owned behind `Shared`.
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    coin: bool,
+    card: bool,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The frames for one packet. Sync and hello have no Brokenithm form and
+    /// produce none.
+    pub fn encode(&mut self, packet: &ClientPacket) -> Frames {
+        let mut frames = Frames::new();
+        let (buttons, air, slider) = match *packet {
+            ClientPacket::FullState { buttons, air, slider } => (buttons, air, slider),
+            ClientPacket::AirSlider { air, slider } => (0, air, slider),
+            ClientPacket::Buttons(buttons) => (buttons, 0, 0),
+            ClientPacket::Card(bcd) => {
+                self.card = true;
+                let mut body = [0u8; 12];
+                body[0] = 1;
+                body[1] = CARD_AIME;
+                body[2..].copy_from_slice(&bcd);
+                frames.push(CARD_TYPE, &body);
+                return frames;
+            }
+            ClientPacket::Ping { id, micros } => {
+                frames.push(PING_TYPE, &ping_value(id, micros).to_le_bytes());
+                return frames;
+            }
+            ClientPacket::Sync { .. } | ClientPacket::Hello { .. } => return frames,
+        };
+
+        let coin = buttons & 0x01 != 0;
+        // A coin press that coincides with a card release goes out next tick.
+        let deferred = self.card && coin && !self.coin;
+        if self.card {
+            self.card = false;
+            frames.push(CARD_TYPE, &[0u8; 12]);
+        } else if coin && !self.coin {
+            frames.push(FUNCTION_TYPE, &[FUNCTION_COIN]);
+        }
+        if !deferred {
+            self.coin = coin;
+        }
+
+        let mut body = [0u8; INPUT_LEN - 4];
+        for (i, cell) in body[..AIR_LEDS].iter_mut().enumerate() {
+            *cell = (air >> i) & 1;
+        }
+        for (i, cell) in body[AIR_LEDS..AIR_LEDS + 32].iter_mut().enumerate() {
+            if (slider >> i) & 1 == 1 {
+                *cell = SLIDER_TOUCHED;
+            }
+        }
+        body[AIR_LEDS + 32] = (buttons >> 2) & 1;
+        body[AIR_LEDS + 33] = (buttons >> 1) & 1;
+        frames.push(INPUT_TYPE, &body);
+        frames
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+fn check(kind: &'static str, bytes: &[u8], expected: usize) -> Result<(), ProtocolError> {
+    if bytes.len() != expected {
+        return Err(ProtocolError::Length { kind, expected, actual: bytes.len() });
+    }
+    Ok(())
+}
+
+/// Checks the size byte and splits the type off the body.
+fn parse(bytes: &[u8]) -> Result<([u8; 3], &[u8]), ProtocolError> {
+    let size = *bytes.first().ok_or(ProtocolError::Empty)? as usize;
+    check("brokenithm frame", bytes, size + 1)?;
+    if bytes.len() < 4 {
+        return Err(ProtocolError::Length { kind: "brokenithm frame", expected: 4, actual: bytes.len() });
+    }
+    Ok(([bytes[1], bytes[2], bytes[3]], &bytes[4..]))
+}
+
+pub fn decode_server(bytes: &[u8]) -> Result<ServerPacket, ProtocolError> {
+    let (kind, body) = parse(bytes)?;
+    match kind {
+        LED_TYPE => {
+            check("brokenithm led", bytes, LED_LEN)?;
+            let mut frame = LedFrame::default();
+            for (i, brg) in body.chunks_exact(3).take(SLIDER_LEDS).enumerate() {
+                frame.slider[SLIDER_LEDS - 1 - i] = [brg[1], brg[2], brg[0]];
+            }
+            Ok(ServerPacket::Led(frame))
+        }
+        PONG_TYPE => {
+            check("brokenithm pong", bytes, PING_LEN)?;
+            let value = u64::from_le_bytes(body.try_into().expect("8 bytes"));
+            Ok(ServerPacket::Pong { id: (value >> 32) as u16, micros: value as u32 })
+        }
+        _ => Err(ProtocolError::UnknownHeader(bytes[1])),
+    }
+}
+
+/// The Brokenithm form of a server packet, for LED and pong only.
+pub fn encode_server(packet: &ServerPacket) -> Option<Vec<u8>> {
+    let (kind, body) = match packet {
+        ServerPacket::Led(frame) => {
+            let mut body = vec![0u8; 3 * LED_CELLS];
+            for (i, rgb) in frame.slider.iter().rev().enumerate() {
+                body[3 * i..3 * i + 3].copy_from_slice(&[rgb[2], rgb[0], rgb[1]]);
+            }
+            (LED_TYPE, body)
+        }
+        ServerPacket::Pong { id, micros } => (PONG_TYPE, ping_value(*id, *micros).to_le_bytes().to_vec()),
+        _ => return None,
+    };
+    let mut out = Vec::with_capacity(4 + body.len());
+    out.push((3 + body.len()) as u8);
+    out.extend_from_slice(&kind);
+    out.extend_from_slice(&body);
+    Some(out)
+}
+
+pub fn decode_client(bytes: &[u8]) -> Result<ClientFrame, ProtocolError> {
+    let (kind, body) = parse(bytes)?;
+    match kind {
+        INPUT_TYPE => {
+            check("brokenithm input", bytes, INPUT_LEN)?;
+            let air = (0..AIR_LEDS).fold(0u8, |acc, i| acc | (u8::from(body[i] != 0) << i));
+            let slider = (0..32).fold(0u32, |acc, i| acc | (u32::from(body[AIR_LEDS + i] != 0) << i));
+            Ok(ClientFrame::Input {
+                air,
+                slider,
+                test: body[AIR_LEDS + 32] != 0,
+                service: body[AIR_LEDS + 33] != 0,
+            })
+        }
+        FUNCTION_TYPE => {
+            check("brokenithm function", bytes, FUNCTION_LEN)?;
+            Ok(ClientFrame::Function(body[0]))
+        }
+        CARD_TYPE => {
+            check("brokenithm card", bytes, CARD_LEN)?;
+            if body[0] == 0 {
+                return Ok(ClientFrame::Card(None));
+            }
+            let mut bcd = [0u8; 10];
+            bcd.copy_from_slice(&body[2..]);
+            Ok(ClientFrame::Card(Some(bcd)))
+        }
+        PING_TYPE => {
+            check("brokenithm ping", bytes, PING_LEN)?;
+            Ok(ClientFrame::Ping(u64::from_le_bytes(body.try_into().expect("8 bytes"))))
+        }
+        _ => Err(ProtocolError::UnknownHeader(bytes[1])),
+    }
+}
+
+/// Splits the first complete frame off a TCP stream, like
+/// `protocol::split_tcp_frame` but using the frame's own size byte.
+pub fn split_frame(buf: &[u8]) -> Option<(&[u8], usize)> {
+    let len = *buf.first()? as usize + 1;
+    (buf.len() >= len).then(|| (&buf[..len], len))
+}
@@ -0,0 +1,116 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use thiserror::Error;
+
+/// Transport selected in the Connection section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Udp = 0,
+    Tcp = 1,
+}
+
+impl TryFrom<u32> for Protocol {
+    type Error = ConfigError;
+
+    fn try_from(raw: u32) -> Result<Self, ConfigError> {
+        match raw {
+            0 => Ok(Protocol::Udp),
+            1 => Ok(Protocol::Tcp),
+            other => Err(ConfigError::UnsupportedProtocol(other)),
+        }
+    }
+}
+
+/// Wire format spoken over the selected transport.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Dialect {
+    /// This project's framing, with the hello exchange and its extensions.
+    #[default]
+    Rustnithm = 0,
+    /// The original Brokenithm packets; see `brokenithm`.
+    Brokenithm = 1,
+}
+
+impl TryFrom<u32> for Dialect {
+    type Error = ConfigError;
+
+    fn try_from(raw: u32) -> Result<Self, ConfigError> {
+        match raw {
+            0 => Ok(Dialect::Rustnithm),
+            1 => Ok(Dialect::Brokenithm),
+            other => Err(ConfigError::UnsupportedProtocol(other)),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("invalid target address {0:?}")]
+    InvalidAddress(String),
+    #[error("could not resolve host {0:?}")]
+    UnresolvedHost(String),
+    #[error("failed to bind local socket: {0}")]
+    Bind(#[source] io::Error),
+    #[error("unsupported protocol type {0}")]
+    UnsupportedProtocol(u32),
+    #[error("engine not initialized")]
+    NotInitialized,
+    #[error("invalid pulse profile: {0}")]
+    InvalidPulseProfile(String),
+}
+
+/// Parses a literal target as sent from Kotlin: `1.2.3.4`, `::1`, `[::1]`,
+/// or a full `[::1]:8200` / `1.2.3.4:8200` whose embedded port wins over
+/// `port`. Hostnames are rejected here; see `resolve_target`.
+pub fn parse_target(host: &str, port: i32) -> Result<SocketAddr, ConfigError> {
+    let host = host.trim();
+    let invalid = || ConfigError::InvalidAddress(format!("{}:{}", host, port));
+
+    if let Ok(addr) = host.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    let port = u16::try_from(port).map_err(|_| invalid())?;
+    let bare = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+    bare.parse::<IpAddr>()
+        .map(|ip| SocketAddr::new(ip, port))
+        .map_err(|_| invalid())
+}
+
+/// Like `parse_target`, but falls back to a DNS lookup for hostnames. This
+/// may block for as long as the resolver takes, so it must never run on the
+/// engine thread.
+pub fn resolve_target(host: &str, port: i32) -> Result<SocketAddr, ConfigError> {
+    if let Ok(addr) = parse_target(host, port) {
+        return Ok(addr);
+    }
+    let host = host.trim();
+    let port = u16::try_from(port)
+        .map_err(|_| ConfigError::InvalidAddress(format!("{}:{}", host, port)))?;
+    if !is_hostname(host) {
+        return Err(ConfigError::InvalidAddress(format!("{}:{}", host, port)));
+    }
+    (host, port)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or_else(|| ConfigError::UnresolvedHost(host.to_string()))
+}
+
+/// RFC 1123 hostname syntax. All-numeric names are left to `parse_target`,
+/// so `300.1.1.1` is an invalid address rather than a failed lookup.
+fn is_hostname(host: &str) -> bool {
+    let host = host.strip_suffix('.').unwrap_or(host);
+    !host.is_empty()
+        && host.len() <= 253
+        && !host.chars().all(|c| c.is_ascii_digit() || c == '.')
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
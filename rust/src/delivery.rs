@@ -2,76 +2,118 @@ use std::io::{Read, Write};
 use std::net::{SocketAddr, TcpStream, UdpSocket};
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Mutex;
-use std::thread;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
-use once_cell::sync::Lazy;
-use crate::{DATA_POOL, PROTOCOL_TYPE, STATE_VALUE};
-
-pub(crate) static TCP_STREAM: Lazy<Mutex<Option<TcpStream>>> = Lazy::new(|| Mutex::new(None));
-
-static TCP_READ_BUF: Lazy<Mutex<Vec<u8>>> = Lazy::new(|| Mutex::new(Vec::with_capacity(64)));
-
-/// Frames queued for the non-blocking TCP socket. A non-blocking `write` may
-/// accept only part of a frame; leftover bytes stay here until flushed, so the
-/// server never sees a torn (misaligned) frame stream.
-static TCP_SEND_PENDING: Lazy<Mutex<Vec<u8>>> = Lazy::new(|| Mutex::new(Vec::new()));
+use crate::auth;
+use crate::brokenithm;
+use crate::config::Dialect;
+use crate::delta::DeltaGate;
+use crate::engine::Shared;
+use crate::protocol::{self, ClientPacket, Features, ServerPacket, Stamp, Transport};
+use crate::reliable::ReliableSender;
+use crate::state::{ConnEvent, ConnState};
+use crate::timer;
 
 /// Upper bound for the send queue. If a peer stops reading, we drop the
 /// connection (and let the engine loop reconnect) instead of growing forever.
 const MAX_TCP_SEND_PENDING: usize = 64 * 1024;
 
-/// Guards against spawning more than one RX thread at a time.
+/// Per-engine TCP connection state.
 ///
-/// Who resets this flag:
-/// - the RX thread itself on self-initiated teardown (EOF / socket error),
-///   BEFORE clearing `TCP_STREAM`, so a reconnect can spawn immediately;
-/// - whoever clears the stream externally (`set_tcp_stream(None)`, or the
-///   sender's write-error path), synchronously, so the flag can never stay
-///   stuck at `true` while a sleeping RX thread is on its way out.
-///
-/// The RX thread's other exit paths (stream already `None`, poisoned mutex) do
-/// NOT reset the flag: the external clearer already did, and resetting again
-/// could wipe a freshly spawned successor's flag.
-static TCP_RX_SPAWNED: AtomicBool = AtomicBool::new(false);
-
-/// Lock order everywhere: TCP_SEND_PENDING -> TCP_STREAM -> TCP_READ_BUF.
+/// Lock order everywhere: `send_pending` -> `stream` -> `read_buf`.
 /// `send_packet_tcp` and `set_tcp_stream` both follow it; `tcp_rx_loop` only
-/// touches TCP_STREAM then TCP_READ_BUF, so no cycle is possible.
-pub fn set_tcp_stream(stream: Option<TcpStream>) {
+/// touches `stream` then `read_buf`, so no cycle is possible.
+pub(crate) struct TcpLink {
+    pub stream: Mutex<Option<TcpStream>>,
+    read_buf: Mutex<Vec<u8>>,
+    /// Frames queued for the non-blocking TCP socket. A non-blocking `write` may
+    /// accept only part of a frame; leftover bytes stay here until flushed, so the
+    /// server never sees a torn (misaligned) frame stream.
+    send_pending: Mutex<Vec<u8>>,
+    /// Guards against spawning more than one RX thread at a time.
+    ///
+    /// Who resets this flag:
+    /// - the RX thread itself on self-initiated teardown (EOF / socket error),
+    ///   BEFORE clearing `stream`, so a reconnect can spawn immediately;
+    /// - whoever clears the stream externally (`set_tcp_stream(None)`, or the
+    ///   sender's write-error path), synchronously, so the flag can never stay
+    ///   stuck at `true` while a sleeping RX thread is on its way out.
+    ///
+    /// The RX thread's other exit paths (stream already `None`, poisoned mutex) do
+    /// NOT reset the flag: the external clearer already did, and resetting again
+    /// could wipe a freshly spawned successor's flag.
+    rx_spawned: AtomicBool,
+    /// RX threads that may still be running, joined on shutdown.
+    rx_threads: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl TcpLink {
+    pub fn new() -> Self {
+        Self {
+            stream: Mutex::new(None),
+            read_buf: Mutex::new(Vec::with_capacity(64)),
+            send_pending: Mutex::new(Vec::new()),
+            rx_spawned: AtomicBool::new(false),
+            rx_threads: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+pub fn set_tcp_stream(shared: &Arc<Shared>, stream: Option<TcpStream>) {
+    let tcp = &shared.tcp;
     let should_spawn_rx = stream.is_some();
 
     // External teardown: reset the spawn flag synchronously so a reconnect can
     // start a fresh RX thread right away, without waiting for the old (sleeping)
     // RX thread to notice the cleared stream.
     if !should_spawn_rx {
-        TCP_RX_SPAWNED.store(false, Ordering::SeqCst);
+        tcp.rx_spawned.store(false, Ordering::SeqCst);
     }
 
-    if let Ok(mut pending) = TCP_SEND_PENDING.lock() {
+    if let Ok(mut pending) = tcp.send_pending.lock() {
         pending.clear();
     }
-    if let Ok(mut guard) = TCP_STREAM.lock() {
+    if let Ok(mut guard) = tcp.stream.lock() {
         *guard = stream;
     }
-    if let Ok(mut buf) = TCP_READ_BUF.lock() {
+    if let Ok(mut buf) = tcp.read_buf.lock() {
         buf.clear();
     }
     if should_spawn_rx {
-        spawn_tcp_rx();
+        spawn_tcp_rx(shared);
     }
 }
 
-fn spawn_tcp_rx() {
-    if TCP_RX_SPAWNED.swap(true, Ordering::SeqCst) {
+fn spawn_tcp_rx(shared: &Arc<Shared>) {
+    if shared.tcp.rx_spawned.swap(true, Ordering::SeqCst) {
         return;
     }
-    if thread::Builder::new()
+    let rx_shared = Arc::clone(shared);
+    match thread::Builder::new()
         .name("RustTcpRx".into())
-        .spawn(tcp_rx_loop)
-        .is_err()
+        .spawn(move || tcp_rx_loop(rx_shared))
     {
-        TCP_RX_SPAWNED.store(false, Ordering::SeqCst);
+        Ok(handle) => {
+            if let Ok(mut threads) = shared.tcp.rx_threads.lock() {
+                threads.retain(|t| !t.is_finished());
+                threads.push(handle);
+            }
+        }
+        Err(_) => shared.tcp.rx_spawned.store(false, Ordering::SeqCst),
+    }
+}
+
+/// Closes the connection and joins every RX thread. Each one notices the
+/// cleared stream within a millisecond and exits.
+pub fn shutdown_tcp(shared: &Arc<Shared>) {
+    set_tcp_stream(shared, None);
+    let threads = match shared.tcp.rx_threads.lock() {
+        Ok(mut threads) => std::mem::take(&mut *threads),
+        Err(_) => return,
+    };
+    for handle in threads {
+        let _ = handle.join();
     }
 }
 
@@ -87,7 +129,8 @@ enum RxAction {
 /// (2-byte LE length prefix) and fed straight into `process_server_frame`,
 /// which is safe to call from this thread (atomics + mutex only).
 #[allow(unused_assignments)] // `my_fd` initial -1 is overwritten before first use, kept as a defensive sentinel
-fn tcp_rx_loop() {
+fn tcp_rx_loop(shared: Arc<Shared>) {
+    let tcp = &shared.tcp;
     let mut tmp = [0u8; 256];
     // Raw fd of the stream this thread is currently reading. Used on teardown
     // to make sure we only clear the connection we actually read, never a fresh
@@ -96,7 +139,7 @@ fn tcp_rx_loop() {
 
     loop {
         let action = {
-            let mut guard = match TCP_STREAM.lock() {
+            let mut guard = match tcp.stream.lock() {
                 Ok(g) => g,
                 Err(_) => break, // poisoned; nothing left to do
             };
@@ -123,36 +166,32 @@ fn tcp_rx_loop() {
 
         match action {
             RxAction::Data(n) => {
-                if let Ok(mut buf) = TCP_READ_BUF.lock() {
+                if let Ok(mut buf) = tcp.read_buf.lock() {
                     buf.extend_from_slice(&tmp[..n]);
 
-                    loop {
-                        if buf.len() < 2 {
-                            break;
-                        }
-                        let frame_len = u16::from_le_bytes([buf[0], buf[1]]) as usize;
-                        if buf.len() < 2 + frame_len {
-                            break;
-                        }
-
-                        let frame = buf[2..2 + frame_len].to_vec();
-                        buf.drain(..2 + frame_len);
+                    let split = match shared.dialect() {
+                        Dialect::Rustnithm => protocol::split_tcp_frame,
+                        Dialect::Brokenithm => brokenithm::split_frame,
+                    };
+                    while let Some((frame, consumed)) = split(&buf) {
+                        let frame = frame.to_vec();
+                        buf.drain(..consumed);
 
-                        process_server_frame(&frame, STATE_VALUE.load(Ordering::Acquire));
+                        process_server_frame(&shared, &frame, shared.state.load());
                     }
                 }
             }
             RxAction::Eof | RxAction::Error => {
-                // Self-initiated teardown, done atomically under the TCP_STREAM
+                // Self-initiated teardown, done atomically under the `stream`
                 // lock: reset the spawn flag and clear the stream together, and
                 // only if the stream in the slot is still the one we read (fd
                 // check). If a reconnect already installed a new connection, we
                 // must not touch it — and must not wipe its RX thread's flag.
-                if let Ok(mut guard) = TCP_STREAM.lock() {
+                if let Ok(mut guard) = tcp.stream.lock() {
                     if let Some(current) = guard.as_ref() {
                         if current.as_raw_fd() == my_fd {
                             *guard = None;
-                            TCP_RX_SPAWNED.store(false, Ordering::SeqCst);
+                            tcp.rx_spawned.store(false, Ordering::SeqCst);
                         }
                     }
                 }
@@ -165,75 +204,188 @@ fn tcp_rx_loop() {
     }
 }
 
-pub fn handle_receive(socket: &UdpSocket, current_state: u32) {
-    if PROTOCOL_TYPE.load(Ordering::Relaxed) == 1 {
+pub fn handle_receive(shared: &Shared, socket: &UdpSocket, current_state: ConnState) {
+    if shared.protocol.load(Ordering::Relaxed) == 1 {
         // TCP: incoming frames are handled by the dedicated RX thread.
         return;
     }
-    handle_receive_udp(socket, current_state);
+    handle_receive_udp(shared, socket, current_state);
 }
 
-pub fn send_packet(socket: &UdpSocket, addr: &SocketAddr, current_state: u32) {
-    if PROTOCOL_TYPE.load(Ordering::Relaxed) == 1 {
-        send_packet_tcp(current_state);
+/// Result of one send attempt, for telemetry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendOutcome {
+    Sent,
+    /// Nothing to send in this state, or nothing new in delta mode.
+    Skipped,
+    Failed,
+}
+
+pub fn send_packet(
+    shared: &Shared,
+    socket: &UdpSocket,
+    addr: &SocketAddr,
+    current_state: ConnState,
+    gate: &mut DeltaGate,
+    now_ns: u64,
+) -> SendOutcome {
+    let Some((packet, id)) = next_packet(shared, current_state, gate, now_ns) else {
+        return SendOutcome::Skipped;
+    };
+    if shared.protocol.load(Ordering::Relaxed) == 1 {
+        send_packet_tcp(shared, packet)
     } else {
-        send_packet_udp(socket, addr, current_state);
+        send_packet_udp(shared, socket, addr, packet, id)
     }
 }
 
-pub fn handle_sync_timeout() {
-    if let Ok(mut guard) = DATA_POOL.sync_deadline.lock() {
-        if let Some(deadline) = *guard {
-            if Instant::now() > deadline {
-                let target = DATA_POOL.sync_target_state.load(Ordering::Relaxed);
-                STATE_VALUE.store(if target == 1 { 0 } else { 1 }, Ordering::SeqCst);
-                *guard = None;
+/// Sends one RTT probe.
+pub fn send_ping(shared: &Shared, socket: &UdpSocket, addr: &SocketAddr) -> SendOutcome {
+    let now = timer::monotonic_ns();
+    let packet = ClientPacket::Ping {
+        id: shared.rtt.on_ping(now),
+        micros: (now / 1_000) as u32,
+    };
+    send_control(shared, socket, addr, packet)
+}
+
+/// Sends a packet outside the tick schedule (ping, hello). These bypass the
+/// send mode and are not counted in the send-loop telemetry.
+pub fn send_control(shared: &Shared, socket: &UdpSocket, addr: &SocketAddr, packet: ClientPacket) -> SendOutcome {
+    if shared.protocol.load(Ordering::Relaxed) == 1 {
+        send_packet_tcp(shared, packet)
+    } else {
+        send_packet_udp(shared, socket, addr, packet, None)
+    }
+}
+
+/// `build_packet`, filtered by the send mode, with the message id if it goes
+/// out as a reliable message. Only input packets are gated: sync requests go
+/// out every tick until the server answers, or on the retransmission
+/// schedule once reliable delivery was agreed (UDP only; TCP needs none).
+/// A held card is an input too, sent every tick for servers that poll it;
+/// reliable delivery only adds an id to its first copy and retransmissions.
+fn next_packet(
+    shared: &Shared,
+    current_state: ConnState,
+    gate: &mut DeltaGate,
+    now_ns: u64,
+) -> Option<(ClientPacket, Option<u16>)> {
+    let packet = build_packet(shared, current_state);
+    if current_state != ConnState::Connected {
+        gate.reset();
+    }
+    if let Some(ClientPacket::Sync { target_connected }) = packet {
+        if shared.dialect() == Dialect::Brokenithm {
+            // Brokenithm servers have no sync; confirm it ourselves.
+            let _ = shared.apply(ConnEvent::ServerConfirm { connected: target_connected });
+            return None;
+        }
+    }
+    if shared.protocol.load(Ordering::Relaxed) == 0 && shared.handshake.uses(Features::RELIABLE) {
+        if let Ok(mut sender) = shared.reliable.lock() {
+            match packet {
+                Some(p) if ReliableSender::is_control(&p) => {
+                    if let Some(id) = sender.poll(&p, now_ns) {
+                        return Some((p, Some(id)));
+                    }
+                    if !matches!(p, ClientPacket::Card(_)) {
+                        return None;
+                    }
+                }
+                _ => sender.idle(),
             }
         }
     }
+    let packet = packet?;
+    if current_state != ConnState::Connected {
+        return Some((packet, None));
+    }
+    gate.admit(shared.send_mode.load(), &packet, now_ns).then_some((packet, None))
 }
 
-fn handle_receive_udp(socket: &UdpSocket, current_state: u32) {
-    let mut recv_buf = [0u8; 2];
-    while let Ok((size, _)) = socket.recv_from(&mut recv_buf) {
-        if size == 2 {
-            process_server_frame(&recv_buf[..2], current_state);
-        }
+pub fn handle_sync_timeout(shared: &Shared) {
+    let expired = match shared.data.sync_deadline.lock() {
+        Ok(guard) => matches!(*guard, Some(deadline) if Instant::now() > deadline),
+        Err(_) => false,
+    };
+    if expired {
+        // A confirm may have landed in between; then the transition is
+        // rejected and the confirmed state stands.
+        let _ = shared.apply(ConnEvent::Timeout);
     }
 }
 
-fn send_packet_udp(socket: &UdpSocket, addr: &SocketAddr, current_state: u32) {
-    if let Some((buf, len)) = build_packet(current_state, false) {
-        let _ = socket.send_to(&buf[..len], addr);
+fn handle_receive_udp(shared: &Shared, socket: &UdpSocket, current_state: ConnState) {
+    let mut recv_buf = [0u8; auth::MAX_SEALED];
+    while let Ok((size, _)) = socket.recv_from(&mut recv_buf) {
+        process_server_frame(shared, &recv_buf[..size], current_state);
     }
 }
 
-fn send_packet_tcp(current_state: u32) {
-    let Some((payload_buf, payload_len)) = build_packet(current_state, true) else {
-        return;
-    };
+fn send_packet_udp(
+    shared: &Shared,
+    socket: &UdpSocket,
+    addr: &SocketAddr,
+    packet: ClientPacket,
+    id: Option<u16>,
+) -> SendOutcome {
+    if let Some(frames) = brokenithm_frames(shared, &packet) {
+        if frames.is_empty() {
+            return SendOutcome::Skipped;
+        }
+        if frames.iter().any(|frame| socket.send_to(frame, addr).is_err()) {
+            return SendOutcome::Failed;
+        }
+        return SendOutcome::Sent;
+    }
+    if !shared.pairing.may_send(&packet) {
+        return SendOutcome::Skipped;
+    }
+    let encoded = packet.encode_message(Transport::Udp, id, next_stamp(shared));
+    let wire = shared.pairing.protect(encoded.as_bytes());
+    match socket.send_to(wire.as_bytes(), addr) {
+        Ok(_) => SendOutcome::Sent,
+        Err(_) => SendOutcome::Failed,
+    }
+}
 
-    let mut framed = Vec::with_capacity(2 + payload_len);
-    let len_bytes = (payload_len as u16).to_le_bytes();
-    framed.extend_from_slice(&len_bytes);
-    framed.extend_from_slice(&payload_buf[..payload_len]);
+fn send_packet_tcp(shared: &Shared, packet: ClientPacket) -> SendOutcome {
+    let tcp = &shared.tcp;
 
-    let mut pending = match TCP_SEND_PENDING.lock() {
+    let brokenithm = brokenithm_frames(shared, &packet);
+    if brokenithm.is_some_and(|frames| frames.is_empty()) {
+        return SendOutcome::Skipped;
+    }
+    if brokenithm.is_none() && !shared.pairing.may_send(&packet) {
+        return SendOutcome::Skipped;
+    }
+    let mut pending = match tcp.send_pending.lock() {
         Ok(p) => p,
-        Err(_) => return,
+        Err(_) => return SendOutcome::Failed,
     };
-    pending.extend_from_slice(&framed);
+    if let Some(frames) = brokenithm {
+        // Brokenithm frames carry their own size byte.
+        for frame in frames.iter() {
+            pending.extend_from_slice(frame);
+        }
+    } else {
+        let encoded = packet.encode_stamped(Transport::Tcp, next_stamp(shared));
+        let wire = shared.pairing.protect(encoded.as_bytes());
+        protocol::write_tcp_frame(wire.as_bytes(), &mut pending);
+    }
 
-    let mut guard = match TCP_STREAM.lock() {
+    let mut guard = match tcp.stream.lock() {
         Ok(g) => g,
-        Err(_) => return,
+        Err(_) => return SendOutcome::Failed,
     };
     if guard.is_none() {
         pending.clear();
-        return;
+        return SendOutcome::Failed;
     }
 
     let stream = guard.as_mut().expect("checked is_some above");
+    let mut outcome = SendOutcome::Sent;
 
     loop {
         match stream.write(&pending) {
@@ -247,9 +399,10 @@ fn send_packet_tcp(current_state: u32) {
             // Send buffer temporarily full: keep the rest queued for next tick.
             Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
             Err(_) => {
-                TCP_RX_SPAWNED.store(false, Ordering::SeqCst);
+                tcp.rx_spawned.store(false, Ordering::SeqCst);
                 *guard = None;
                 pending.clear();
+                outcome = SendOutcome::Failed;
                 break;
             }
         }
@@ -260,72 +413,102 @@ fn send_packet_tcp(current_state: u32) {
     // set_tcp_stream clears the pending queue. Checked AFTER the flush so a
     // peer that just resumed reading is not torn down needlessly.
     if pending.len() > MAX_TCP_SEND_PENDING {
-        TCP_RX_SPAWNED.store(false, Ordering::SeqCst);
+        tcp.rx_spawned.store(false, Ordering::SeqCst);
         *guard = None;
         pending.clear();
+        outcome = SendOutcome::Failed;
     }
+    outcome
 }
 
-fn process_server_frame(frame: &[u8], current_state: u32) {
-    if frame.len() < 2 {
-        return;
+/// `packet` in Brokenithm's format, or `None` in the native dialect.
+fn brokenithm_frames(shared: &Shared, packet: &ClientPacket) -> Option<brokenithm::Frames> {
+    if shared.dialect() != Dialect::Brokenithm {
+        return None;
     }
-    let header = frame[0];
-    let payload = frame[1];
+    let mut session = shared.brokenithm.lock().ok()?;
+    Some(session.encode(packet))
+}
 
-    if (header >> 6) & 1 == 1 && (header & 0x30) == 0 && current_state == 2 {
-        let server_confirm = (payload >> 4) & 1;
-        if (server_confirm as u32) == DATA_POOL.sync_target_state.load(Ordering::Relaxed) {
-            STATE_VALUE.store(server_confirm as u32, Ordering::SeqCst);
-            if let Ok(mut guard) = DATA_POOL.sync_deadline.lock() {
-                *guard = None;
-            }
-        }
+/// Takes the next sequence number if stamping was agreed on. The timestamp
+/// is taken here, right before the packet is handed to the socket.
+fn next_stamp(shared: &Shared) -> Option<Stamp> {
+    if !shared.handshake.uses(Features::STAMP) {
+        return None;
     }
+    Some(Stamp {
+        seq: shared.seq.fetch_add(1, Ordering::Relaxed) as u16,
+        micros: (timer::monotonic_ns() / 1_000) as u32,
+    })
 }
 
-fn build_packet(current_state: u32, is_tcp: bool) -> Option<([u8; 11], usize)> {
-    let p_type = match current_state {
-        2 => 0,
-        1 => DATA_POOL.packet_type.load(Ordering::Relaxed),
-        _ => return None,
-    };
-
-    let mut buffer = [0u8; 11];
-    let protocol_bit = if is_tcp { 0x80u8 } else { 0x00u8 };
-    let type_bits: u8 = match p_type {
-        0 => 0b00,
-        16 => 0b01,
-        32 => 0b10,
-        48 => 0b11,
-        _ => 0b01,
+fn process_server_frame(shared: &Shared, frame: &[u8], current_state: ConnState) {
+    let decoded = match shared.dialect() {
+        Dialect::Rustnithm => {
+            // While paired, frames that fail authentication are dropped (and counted).
+            let Some(frame) = shared.pairing.accept(frame) else { return };
+            ServerPacket::decode(frame.as_bytes())
+        }
+        // Brokenithm servers cannot pair.
+        Dialect::Brokenithm => brokenithm::decode_server(frame),
     };
-    buffer[0] = protocol_bit | (type_bits << 4);
-
-    let packet_len = match p_type {
-        0 => {
-            let target = DATA_POOL.sync_target_state.load(Ordering::Relaxed);
-            buffer[1] = if target == 0 { 1 << 7 } else { (1 << 5) | (1 << 4) };
-            2
+    match decoded {
+        Ok(ServerPacket::SyncConfirm { connected }) => {
+            if let Ok(mut sender) = shared.reliable.lock() {
+                sender.on_sync_confirm(connected);
+            }
+            if let ConnState::Syncing { .. } = current_state {
+                // A confirm for the other direction is stale; the state machine
+                // rejects it and we keep waiting.
+                let _ = shared.apply(ConnEvent::ServerConfirm { connected });
+            }
         }
-        16 => {
-            buffer[1] = DATA_POOL.button_mask.load(Ordering::Relaxed) as u8;
-            2
+        Ok(ServerPacket::Led(frame)) => {
+            if shared.handshake.uses(Features::LED) {
+                shared.leds.publish(&frame);
+            }
         }
-        32 => {
-            buffer[1] = DATA_POOL.air_byte.load(Ordering::Relaxed) as u8;
-            let s_mask = DATA_POOL.slider_mask.load(Ordering::Relaxed);
-            buffer[2..6].copy_from_slice(&s_mask.to_le_bytes());
-            6
+        Ok(ServerPacket::HelloAck { version, features, nonce }) => {
+            shared.pairing.set_peer_nonce(nonce);
+            shared.handshake.on_ack(version, features);
+            // Newly agreed features (ping) may need a parked loop.
+            shared.waker.notify();
         }
-        48 => {
-            if let Ok(guard) = DATA_POOL.card_bcd.lock() {
-                buffer[1..11].copy_from_slice(&*guard);
+        Ok(ServerPacket::Pong { id, .. }) => shared.rtt.on_pong(id, timer::monotonic_ns()),
+        Ok(ServerPacket::Ack { id }) => {
+            if let Ok(mut sender) = shared.reliable.lock() {
+                sender.on_ack(id);
             }
-            11
         }
-        _ => return None,
+        Err(_) => {}
+    }
+}
+
+fn build_packet(shared: &Shared, current_state: ConnState) -> Option<ClientPacket> {
+    let data = &shared.data;
+    let p_type = match current_state {
+        ConnState::Syncing { target_connected } => {
+            return Some(ClientPacket::Sync { target_connected });
+        }
+        ConnState::Connected => data.packet_type.load(Ordering::Relaxed),
+        ConnState::Idle => return None,
     };
 
-    Some((buffer, packet_len))
+    let inputs = data.inputs();
+    // Brokenithm's input frame always carries everything.
+    let full_state = shared.handshake.uses(Features::FULL_STATE) || shared.dialect() == Dialect::Brokenithm;
+    match p_type {
+        16 | 32 if full_state => Some(ClientPacket::FullState {
+            buttons: inputs.buttons,
+            air: inputs.air,
+            slider: inputs.slider,
+        }),
+        16 => Some(ClientPacket::Buttons(inputs.buttons)),
+        32 => Some(ClientPacket::AirSlider { air: inputs.air, slider: inputs.slider }),
+        48 => {
+            let bcd = data.card_bcd.lock().map(|g| *g).unwrap_or([0u8; 10]);
+            Some(ClientPacket::Card(bcd))
+        }
+        _ => None,
+    }
 }
@@ -0,0 +1,95 @@
+//! Change-driven sending.
+//!
+//! In `Delta` mode a packet goes out on the first tick after the input
+//! changed, then `repeats` more times in case one is lost, and after that
+//! only every `keepalive_ns` until the next change. The decision is pure, so
+//! it runs against a simulated clock in tests like `TickScheduler`.
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use crate::protocol::ClientPacket;
+
+pub const DEFAULT_REPEATS: u32 = 3;
+pub const DEFAULT_KEEPALIVE_NS: u64 = 100_000_000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SendMode {
+    /// Send on every tick.
+    #[default]
+    Continuous,
+    /// Send on change, repeat `repeats` times, then every `keepalive_ns`.
+    Delta { repeats: u32, keepalive_ns: u64 },
+}
+
+/// Lock-free holder for a `SendMode`; `u64::MAX` as keepalive means `Continuous`.
+pub(crate) struct SendModeCell {
+    repeats: AtomicU32,
+    keepalive_ns: AtomicU64,
+}
+
+impl SendModeCell {
+    pub fn new(mode: SendMode) -> Self {
+        let cell = Self {
+            repeats: AtomicU32::new(0),
+            keepalive_ns: AtomicU64::new(u64::MAX),
+        };
+        cell.store(mode);
+        cell
+    }
+
+    pub fn load(&self) -> SendMode {
+        match self.keepalive_ns.load(Ordering::Relaxed) {
+            u64::MAX => SendMode::Continuous,
+            keepalive_ns => SendMode::Delta {
+                repeats: self.repeats.load(Ordering::Relaxed),
+                keepalive_ns,
+            },
+        }
+    }
+
+    pub fn store(&self, mode: SendMode) {
+        let (repeats, keepalive_ns) = match mode {
+            SendMode::Continuous => (0, u64::MAX),
+            SendMode::Delta { repeats, keepalive_ns } => (repeats, keepalive_ns.min(u64::MAX - 1)),
+        };
+        self.repeats.store(repeats, Ordering::Relaxed);
+        self.keepalive_ns.store(keepalive_ns, Ordering::Relaxed);
+    }
+}
+
+/// Remembers what was sent last. Owned by the engine thread.
+#[derive(Debug, Clone, Default)]
+pub struct DeltaGate {
+    last: Option<ClientPacket>,
+    repeats_left: u32,
+    last_sent_ns: u64,
+}
+
+impl DeltaGate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `packet`, due on a tick at `now_ns`, should go on the wire.
+    pub fn admit(&mut self, mode: SendMode, packet: &ClientPacket, now_ns: u64) -> bool {
+        let SendMode::Delta { repeats, keepalive_ns } = mode else {
+            return true;
+        };
+        if self.last != Some(*packet) {
+            self.last = Some(*packet);
+            self.repeats_left = repeats;
+        } else if self.repeats_left > 0 {
+            self.repeats_left -= 1;
+        } else if now_ns.saturating_sub(self.last_sent_ns) < keepalive_ns {
+            return false;
+        }
+        self.last_sent_ns = now_ns;
+        true
+    }
+
+    /// Forgets the last packet, so the next one is sent as a change. Used
+    /// whenever the loop paused or the connection was replaced.
+    pub fn reset(&mut self) {
+        self.last = None;
+        self.repeats_left = 0;
+    }
+}
@@ -0,0 +1,220 @@
+//! LAN server discovery.
+//!
+//! The client broadcasts a probe to `DISCOVERY_PORT` and collects the
+//! announcements that come back. The server's address is the source of its
+//! reply; the announcement carries its name, game port and transports.
+//!
+//! Probe:    `R N ? version`
+//! Announce: `R N ! version transports port(LE u16) name_len name(UTF-8)`
+
+use std::collections::HashSet;
+use std::io::{self, ErrorKind};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use crate::protocol::{ProtocolError, PROTOCOL_VERSION};
+
+/// Where servers listen for probes, next to the default game port 8200.
+pub const DISCOVERY_PORT: u16 = 8201;
+/// Longest server name on the wire; longer names are cut.
+pub const MAX_NAME_LEN: usize = 64;
+const PROBE_MAGIC: [u8; 3] = *b"RN?";
+const ANNOUNCE_MAGIC: [u8; 3] = *b"RN!";
+const ANNOUNCE_HEADER: usize = 8;
+/// How often the responder wakes up to check for shutdown.
+const POLL: Duration = Duration::from_millis(20);
+
+/// Transports a server accepts, as a bit set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Transports(pub u8);
+
+impl Transports {
+    pub const NONE: Self = Self(0);
+    pub const UDP: Self = Self(1 << 0);
+    pub const TCP: Self = Self(1 << 1);
+    pub const BOTH: Self = Self(0b11);
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+/// What a server says about itself.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Announce {
+    pub name: String,
+    /// Game port, which need not be the discovery port.
+    pub port: u16,
+    pub transports: Transports,
+    pub version: u8,
+}
+
+impl Announce {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut cut = self.name.len().min(MAX_NAME_LEN);
+        while !self.name.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        let name = &self.name.as_bytes()[..cut];
+        let mut out = Vec::with_capacity(ANNOUNCE_HEADER + name.len());
+        out.extend_from_slice(&ANNOUNCE_MAGIC);
+        out.push(self.version);
+        out.push(self.transports.0);
+        out.extend_from_slice(&self.port.to_le_bytes());
+        out.push(name.len() as u8);
+        out.extend_from_slice(name);
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        if bytes.len() < ANNOUNCE_HEADER {
+            return Err(ProtocolError::Length { kind: "announce", expected: ANNOUNCE_HEADER, actual: bytes.len() });
+        }
+        if bytes[..3] != ANNOUNCE_MAGIC {
+            return Err(ProtocolError::UnknownHeader(bytes[0]));
+        }
+        let name_len = bytes[7] as usize;
+        let expected = ANNOUNCE_HEADER + name_len;
+        if bytes.len() != expected {
+            return Err(ProtocolError::Length { kind: "announce", expected, actual: bytes.len() });
+        }
+        Ok(Self {
+            name: String::from_utf8_lossy(&bytes[ANNOUNCE_HEADER..]).into_owned(),
+            port: u16::from_le_bytes([bytes[5], bytes[6]]),
+            transports: Transports(bytes[4]),
+            version: bytes[3],
+        })
+    }
+}
+
+pub fn probe() -> [u8; 4] {
+    [PROBE_MAGIC[0], PROBE_MAGIC[1], PROBE_MAGIC[2], PROTOCOL_VERSION]
+}
+
+/// A server that answered a probe.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DiscoveredServer {
+    /// Reply source IP with the announced game port.
+    pub addr: SocketAddr,
+    pub name: String,
+    pub transports: Transports,
+    pub version: u8,
+}
+
+/// The IPv4 limited broadcast on `DISCOVERY_PORT`.
+pub fn broadcast_target() -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::BROADCAST, DISCOVERY_PORT))
+}
+
+/// Probes every target (see `broadcast_target`) and collects replies until
+/// `timeout` ends. The probe is sent again halfway in case it was lost.
+/// Blocks for the whole `timeout`; servers appear in reply order, once each.
+/// All targets must be of one address family, as they share one socket.
+/// Any other socket error than the read timing out or being interrupted ends
+/// the search early, with the servers found so far or, if there are none,
+/// with the error.
+pub fn discover(targets: &[SocketAddr], timeout: Duration) -> io::Result<Vec<DiscoveredServer>> {
+    let v6 = targets.first().is_some_and(SocketAddr::is_ipv6);
+    if targets.iter().any(|target| target.is_ipv6() != v6) {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "discovery targets mix IPv4 and IPv6"));
+    }
+    let bind: SocketAddr = if v6 {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(bind)?;
+    if !v6 {
+        socket.set_broadcast(true)?;
+    }
+    let send_probes = || {
+        for target in targets {
+            // One unreachable target must not hide the others.
+            let _ = socket.send_to(&probe(), target);
+        }
+    };
+
+    let start = Instant::now();
+    let deadline = start + timeout;
+    let mut resent = false;
+    let mut seen = HashSet::new();
+    let mut servers = Vec::new();
+    let mut buf = [0u8; ANNOUNCE_HEADER + MAX_NAME_LEN];
+    send_probes();
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        if !resent && now >= start + timeout / 2 {
+            send_probes();
+            resent = true;
+        }
+        let wait = if resent { deadline - now } else { (start + timeout / 2).saturating_duration_since(now) };
+        socket.set_read_timeout(Some(wait.max(Duration::from_millis(1))))?;
+        let (size, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => continue,
+            Err(e) if servers.is_empty() => return Err(e),
+            Err(_) => break,
+        };
+        let Ok(announce) = Announce::decode(&buf[..size]) else { continue };
+        let server = DiscoveredServer {
+            addr: SocketAddr::new(from.ip(), announce.port),
+            name: announce.name,
+            transports: announce.transports,
+            version: announce.version,
+        };
+        if seen.insert(server.addr) {
+            servers.push(server);
+        }
+    }
+    Ok(servers)
+}
+
+/// Answers probes with a fixed announcement: the stand-in for a server's
+/// discovery side, used by the `rustnithm-loopback` binary and by tests.
+pub struct Responder {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Responder {
+    pub fn bind(addr: SocketAddr, announce: Announce) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_read_timeout(Some(POLL))?;
+        let addr = socket.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+        let reply = announce.encode();
+        let thread = thread::Builder::new()
+            .name("DiscoveryResponder".into())
+            .spawn(move || {
+                let mut buf = [0u8; 16];
+                while !thread_stop.load(Ordering::Relaxed) {
+                    if let Ok((size, peer)) = socket.recv_from(&mut buf) {
+                        if buf[..size].starts_with(&PROBE_MAGIC) {
+                            let _ = socket.send_to(&reply, peer);
+                        }
+                    }
+                }
+            })?;
+        Ok(Self { addr, stop, thread: Some(thread) })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for Responder {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.thread.take() {
+            let _ = handle.join();
+        }
+    }
+}
@@ -0,0 +1,778 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use crate::{air::{self, AirGeometry, AirTracker, FlickConfig, FlickDetector}, brokenithm, delivery, handshake::Handshake, led::LedBuffer, pulse::{PulseProfile, PulseState, RetriggerPolicy}};
+use crate::config::{self, ConfigError, Dialect, Protocol};
+use crate::scheduler::{MissPolicy, TickScheduler};
+use crate::delivery::SendOutcome;
+use crate::delta::{DeltaGate, SendMode, SendModeCell};
+use crate::protocol::{Features, LedFrame};
+use crate::auth::{AuthStats, Direction, Pairing};
+use crate::reliable::ReliableSender;
+use crate::rtt::{RttSnapshot, RttTracker};
+use crate::state::{ConnEvent, ConnState, IllegalTransition, StateCell};
+use crate::telemetry::{Telemetry, TelemetrySnapshot};
+use crate::timer::{self, TimerCell, TimerConfig, Waker};
+
+/// Buttons, air and slider as one value, so the send loop never reads half
+/// of an update.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Inputs {
+    pub buttons: u8,
+    pub air: u8,
+    pub slider: u32,
+}
+
+impl Inputs {
+    fn pack(self) -> u64 {
+        (self.buttons as u64) << 40 | (self.air as u64) << 32 | self.slider as u64
+    }
+
+    fn unpack(raw: u64) -> Self {
+        Self {
+            buttons: (raw >> 40) as u8,
+            air: (raw >> 32) as u8,
+            slider: raw as u32,
+        }
+    }
+}
+
+pub(crate) struct NetData {
+    pub packet_type: AtomicU32,
+    /// `Inputs::pack`ed; written by the UI and, for air, by the pulse.
+    inputs: AtomicU64,
+    pub card_bcd: Mutex<[u8; 10]>,
+    pub sync_deadline: Mutex<Option<Instant>>,
+    pub air_mode: AtomicU32,
+    pub mickey: AtomicU32,
+    /// Flicks not yet handed to the pulse; counted, so several between two
+    /// samples are not merged into one.
+    pub pending_flicks: AtomicU32,
+    /// Like `pending_flicks`, for the air-down sweep.
+    pub pending_air_downs: AtomicU32,
+}
+
+impl NetData {
+    fn new() -> Self {
+        Self {
+            packet_type: AtomicU32::new(16),
+            inputs: AtomicU64::new(0),
+            card_bcd: Mutex::new([0u8; 10]),
+            sync_deadline: Mutex::new(None),
+            air_mode: AtomicU32::new(1),
+            mickey: AtomicU32::new(0),
+            pending_flicks: AtomicU32::new(0),
+            pending_air_downs: AtomicU32::new(0),
+        }
+    }
+
+    /// Back to the values of `new`, so a restarted engine starts clean.
+    fn reset(&self) {
+        self.packet_type.store(16, Ordering::Relaxed);
+        self.inputs.store(0, Ordering::Relaxed);
+        if let Ok(mut guard) = self.card_bcd.lock() { *guard = [0u8; 10]; }
+        if let Ok(mut guard) = self.sync_deadline.lock() { *guard = None; }
+        self.air_mode.store(1, Ordering::Relaxed);
+        self.mickey.store(0, Ordering::Relaxed);
+        self.pending_flicks.store(0, Ordering::Relaxed);
+        self.pending_air_downs.store(0, Ordering::Relaxed);
+    }
+
+    pub fn inputs(&self) -> Inputs {
+        Inputs::unpack(self.inputs.load(Ordering::Acquire))
+    }
+
+    pub fn set_inputs(&self, inputs: Inputs) {
+        self.inputs.store(inputs.pack(), Ordering::Release);
+    }
+
+    /// Replaces only the air byte, keeping buttons and slider as they are.
+    pub fn set_air(&self, air: u8) {
+        let _ = self.inputs.fetch_update(Ordering::AcqRel, Ordering::Acquire, |raw| {
+            Some(Inputs { air, ..Inputs::unpack(raw) }.pack())
+        });
+    }
+}
+
+/// How long a sync request waits for the server before reverting.
+const SYNC_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Wildcard bind address of the same family as `target`, so IPv6 targets
+/// get an IPv6 socket.
+fn wildcard_for(target: &SocketAddr) -> SocketAddr {
+    match target {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    }
+}
+
+/// Period of `air::process_flick_sampling`.
+const FLICK_INTERVAL_NS: u64 = 1_600_000;
+
+/// Everything the engine thread, the TCP RX thread and the public API touch.
+/// Owned by one `Engine` and handed to its threads as an `Arc`.
+pub(crate) struct Shared {
+    /// Cleared by `Engine::shutdown`; the engine thread exits when it sees it.
+    pub running: AtomicBool,
+    pub state: StateCell,
+    pub protocol: AtomicU32,
+    /// `Dialect` as its discriminant.
+    pub dialect: AtomicU32,
+    pub interval_ns: AtomicU64,
+    /// `MissPolicy` packed by `MissPolicy::to_raw`.
+    pub miss_policy: AtomicU32,
+    pub timer: TimerCell,
+    pub send_mode: SendModeCell,
+    pub telemetry: Telemetry,
+    /// RTT probe period; 0 = no pings.
+    pub ping_interval_ns: AtomicU64,
+    pub rtt: RttTracker,
+    /// Decides which optional features are in use.
+    pub handshake: Handshake,
+    /// Seals outgoing and checks incoming frames while a key is set.
+    pub pairing: Pairing,
+    /// Retransmits sync and card messages until acked, if agreed on.
+    pub reliable: Mutex<ReliableSender>,
+    /// Coin and card edges in `Dialect::Brokenithm`.
+    pub brokenithm: Mutex<brokenithm::Session>,
+    /// Next stamp sequence number; only the low 16 bits go on the wire.
+    pub seq: AtomicU32,
+    /// Wakes the engine thread when it is parked while idle.
+    pub waker: Waker,
+    pub target_addr: RwLock<Option<SocketAddr>>,
+    pub socket: RwLock<Option<UdpSocket>>,
+    pub data: NetData,
+    pub leds: LedBuffer,
+    pub tcp: delivery::TcpLink,
+    pub pulse: Mutex<PulseState>,
+    /// Pointers in the air area, for Native mode.
+    pub air: Mutex<AirTracker>,
+    /// Turns the touch stream into flicks for Flick mode, if enabled.
+    pub flick: Mutex<FlickDetector>,
+}
+
+/// Message ids start from the clock, so they differ between sessions.
+fn first_message_id() -> u16 {
+    (timer::monotonic_ns() / 1_000) as u16
+}
+
+impl Shared {
+    pub fn dialect(&self) -> Dialect {
+        match self.dialect.load(Ordering::Relaxed) {
+            1 => Dialect::Brokenithm,
+            _ => Dialect::Rustnithm,
+        }
+    }
+
+    /// Starts the hello exchange over under a new pairing session, so no
+    /// server frame sealed for the previous one is accepted.
+    pub fn restart_handshake(&self) {
+        self.pairing.new_session();
+        self.handshake.restart();
+    }
+
+    pub fn reset_reliable(&self) {
+        if let Ok(mut sender) = self.reliable.lock() {
+            sender.reset(first_message_id());
+        }
+    }
+
+    fn new() -> Self {
+        Self {
+            running: AtomicBool::new(false),
+            state: StateCell::new(),
+            protocol: AtomicU32::new(0),
+            dialect: AtomicU32::new(Dialect::default() as u32),
+            interval_ns: AtomicU64::new(1_000_000),
+            miss_policy: AtomicU32::new(MissPolicy::default().to_raw()),
+            timer: TimerCell::new(TimerConfig::default()),
+            send_mode: SendModeCell::new(SendMode::default()),
+            telemetry: Telemetry::new(),
+            ping_interval_ns: AtomicU64::new(0),
+            rtt: RttTracker::new(),
+            handshake: Handshake::new(),
+            pairing: Pairing::new(Direction::ToServer),
+            reliable: Mutex::new(ReliableSender::new(first_message_id())),
+            brokenithm: Mutex::new(brokenithm::Session::new()),
+            seq: AtomicU32::new(0),
+            waker: Waker::new(),
+            target_addr: RwLock::new(None),
+            socket: RwLock::new(None),
+            data: NetData::new(),
+            leds: LedBuffer::new(),
+            tcp: delivery::TcpLink::new(),
+            pulse: Mutex::new(PulseState::new()),
+            air: Mutex::new(AirTracker::new()),
+            flick: Mutex::new(FlickDetector::new()),
+        }
+    }
+
+    /// Runs `event` through the state machine and keeps the sync deadline in
+    /// step with it: armed on entering `Syncing`, cleared on leaving it.
+    pub fn apply(&self, event: ConnEvent) -> Result<ConnState, IllegalTransition> {
+        let next = self.state.apply(event)?;
+        if let Ok(mut guard) = self.data.sync_deadline.lock() {
+            *guard = match next {
+                ConnState::Syncing { .. } => Some(Instant::now() + SYNC_TIMEOUT),
+                _ => None,
+            };
+        }
+        self.waker.notify();
+        Ok(next)
+    }
+}
+
+/// One input snapshot pushed from the UI, mirroring `nativeUpdateState`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InputUpdate {
+    pub packet_type: u32,
+    pub button_mask: u32,
+    pub air_byte: u32,
+    pub slider_mask: u32,
+    pub air_mode: u32,
+    /// Only stored when `packet_type` is 48 (card).
+    pub card_bcd: Option<[u8; 10]>,
+}
+
+/// A self-contained client: connection state, sockets, pulse controller and
+/// the `RustNetEngine` worker thread. Several engines can live in one process.
+pub struct Engine {
+    shared: Arc<Shared>,
+    worker: Mutex<Option<JoinHandle<()>>>,
+    /// Explicit local address; `None` binds the wildcard of the target's family.
+    bind_addr: RwLock<Option<SocketAddr>>,
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self {
+            shared: Arc::new(Shared::new()),
+            worker: Mutex::new(None),
+            bind_addr: RwLock::new(None),
+        }
+    }
+
+    /// Sets the send frequency and spawns the worker if it is not running,
+    /// including after a `shutdown`.
+    pub fn start(&self, freq: u32) {
+        let ns = 1_000_000_000 / (freq.max(1) as u64);
+        self.shared.interval_ns.store(ns, Ordering::SeqCst);
+
+        if let Ok(mut worker) = self.worker.lock() {
+            if worker.is_none() {
+                self.shared.running.store(true, Ordering::SeqCst);
+                let shared = Arc::clone(&self.shared);
+                let handle = thread::Builder::new()
+                    .name("RustNetEngine".into())
+                    .spawn(move || run(shared))
+                    .expect("Failed to spawn RustNetEngine");
+                *worker = Some(handle);
+            }
+        }
+    }
+
+    /// How the send loop treats slots it woke up too late for.
+    pub fn set_miss_policy(&self, policy: MissPolicy) {
+        self.shared.miss_policy.store(policy.to_raw(), Ordering::Relaxed);
+    }
+
+    /// Stops the engine thread and every TCP RX thread, closes both sockets
+    /// and resets inputs, target and state. Returns once all threads are
+    /// joined (at most one TCP connect timeout). `start` brings it back.
+    pub fn shutdown(&self) {
+        let shared = &self.shared;
+        let worker = self.worker.lock().ok().and_then(|mut w| w.take());
+        shared.running.store(false, Ordering::SeqCst);
+        shared.waker.notify();
+        if let Some(handle) = worker {
+            let _ = handle.join();
+        }
+
+        delivery::shutdown_tcp(shared);
+        if let Ok(mut guard) = shared.socket.write() { *guard = None; }
+        if let Ok(mut guard) = shared.target_addr.write() { *guard = None; }
+        shared.protocol.store(0, Ordering::SeqCst);
+
+        let _ = shared.apply(ConnEvent::Disconnect);
+        shared.data.reset();
+        shared.seq.store(0, Ordering::Relaxed);
+        shared.leds.clear();
+        shared.rtt.reset();
+        shared.restart_handshake();
+        shared.reset_reliable();
+        if let Ok(mut session) = shared.brokenithm.lock() { session.reset(); }
+        if let Ok(mut pulse) = shared.pulse.lock() { pulse.stop(); }
+        if let Ok(mut tracker) = shared.air.lock() { tracker.clear(); }
+        if let Ok(mut detector) = shared.flick.lock() { detector.clear(); }
+    }
+
+    pub fn telemetry(&self) -> TelemetrySnapshot {
+        self.shared.telemetry.snapshot(self.shared.interval_ns.load(Ordering::Relaxed))
+    }
+
+    pub fn reset_telemetry(&self) {
+        self.shared.telemetry.reset();
+    }
+
+    /// Sends a ping every `interval` (`None` = never), also while idle, to
+    /// keep `rtt` current. Only servers that agreed to `Features::PING` are
+    /// pinged.
+    pub fn set_ping_interval(&self, interval: Option<Duration>) {
+        let ns = interval.map_or(0, |i| (i.as_nanos() as u64).max(1));
+        self.shared.ping_interval_ns.store(ns, Ordering::Relaxed);
+        self.shared.waker.notify();
+    }
+
+    pub fn rtt(&self) -> RttSnapshot {
+        self.shared.rtt.snapshot()
+    }
+
+    /// How the engine thread waits between iterations.
+    pub fn set_timer(&self, config: TimerConfig) {
+        self.shared.timer.store(config);
+        self.shared.waker.notify();
+    }
+
+    /// Features offered in the hello; all by default. Each one is used only
+    /// if the server acknowledges it too. Changing the offer re-runs the
+    /// handshake.
+    pub fn set_features(&self, features: Features) {
+        self.shared.handshake.set_offered(features);
+        self.shared.pairing.new_session();
+        self.shared.waker.notify();
+    }
+
+    /// Server version and the features both sides agreed on; `None` until
+    /// the server answered the hello, and for servers without handshake.
+    pub fn negotiated(&self) -> Option<(u8, Features)> {
+        self.shared.handshake.negotiated()
+    }
+
+    /// Wire format for the server; kept across restarts like the features.
+    /// `Dialect::Brokenithm` skips the hello and assumes LED and ping, the
+    /// only extras such servers have, and confirms sync requests locally.
+    pub fn set_dialect(&self, dialect: Dialect) {
+        let shared = &self.shared;
+        shared.dialect.store(dialect as u32, Ordering::Relaxed);
+        let fixed = (dialect == Dialect::Brokenithm).then_some(Features::LED | Features::PING);
+        shared.handshake.set_fixed(fixed);
+        shared.pairing.new_session();
+        if let Ok(mut session) = shared.brokenithm.lock() { session.reset(); }
+        // Anything queued or half-read on the stream is in the old format.
+        delivery::set_tcp_stream(shared, None);
+        shared.waker.notify();
+    }
+
+    pub fn dialect(&self) -> Dialect {
+        self.shared.dialect()
+    }
+
+    /// Shared key for pairing mode; `None` (or an empty key) turns it off.
+    /// While paired every frame is sealed, and server frames that are not
+    /// authentic or are replays are dropped and counted in `auth_stats`.
+    /// A new key re-runs the handshake, which carries the session nonces.
+    pub fn set_pairing_key(&self, key: Option<&[u8]>) {
+        if self.shared.pairing.set_key(key.map(<[u8]>::to_vec)) {
+            self.shared.handshake.restart();
+            self.shared.waker.notify();
+        }
+    }
+
+    pub fn is_paired(&self) -> bool {
+        self.shared.pairing.is_paired()
+    }
+
+    pub fn auth_stats(&self) -> AuthStats {
+        self.shared.pairing.stats()
+    }
+
+    /// Every tick, or only on change plus repeats and keepalives.
+    pub fn set_send_mode(&self, mode: SendMode) {
+        self.shared.send_mode.store(mode);
+    }
+
+    /// Local address the engine socket binds to; port 0 picks any. `None`
+    /// restores the default wildcard of the target's family.
+    pub fn set_bind_addr(&self, addr: Option<SocketAddr>) {
+        if let Ok(mut guard) = self.bind_addr.write() { *guard = addr; }
+    }
+
+    /// Resolves `host` (IP literal, `[v6]`, `[v6]:port` or hostname) on the
+    /// calling thread, then applies it like `configure`. Returns the address
+    /// actually used.
+    pub fn configure_host(&self, host: &str, port: i32, protocol_type: u32) -> Result<SocketAddr, ConfigError> {
+        // Cheap checks first, so a bad call never waits on DNS.
+        Protocol::try_from(protocol_type)?;
+        if !self.is_running() {
+            return Err(ConfigError::NotInitialized);
+        }
+        let addr = config::resolve_target(host, port)?;
+        self.configure(addr, protocol_type)?;
+        Ok(addr)
+    }
+
+    fn is_running(&self) -> bool {
+        self.worker.lock().map(|w| w.is_some()).unwrap_or(false)
+    }
+
+    /// Points the engine at `addr`. Everything is validated before any state
+    /// changes, so a failed call leaves the previous configuration in place.
+    pub fn configure(&self, addr: SocketAddr, protocol_type: u32) -> Result<(), ConfigError> {
+        let shared = &self.shared;
+        let protocol = Protocol::try_from(protocol_type)?;
+        if !self.is_running() {
+            return Err(ConfigError::NotInitialized);
+        }
+
+        // Over TCP this socket only satisfies the loop's "socket present"
+        // check; the stream itself is connected by the engine loop.
+        let bind_addr = self.bind_addr.read().ok()
+            .and_then(|a| *a)
+            .unwrap_or_else(|| wildcard_for(&addr));
+        let socket = UdpSocket::bind(bind_addr).map_err(ConfigError::Bind)?;
+        socket.set_nonblocking(true).map_err(ConfigError::Bind)?;
+
+        if let Ok(mut guard) = shared.target_addr.write() { *guard = Some(addr); }
+        shared.protocol.store(protocol as u32, Ordering::SeqCst);
+        delivery::set_tcp_stream(shared, None);
+        if let Ok(mut guard) = shared.socket.write() { *guard = Some(socket); }
+        shared.restart_handshake();
+        shared.reset_reliable();
+        // No connect here: the engine loop owns reconnection and will pick
+        // up the target on its next pass (within ~50 ms).
+        shared.waker.notify();
+        Ok(())
+    }
+
+    /// The newest LED frame from the server and its version (0 = none yet).
+    pub fn leds(&self) -> (u64, LedFrame) {
+        self.shared.leds.read()
+    }
+
+    /// Bumped on every LED frame; cheap enough to poll every UI frame.
+    pub fn led_version(&self) -> u64 {
+        self.shared.leds.version()
+    }
+
+    pub fn state(&self) -> ConnState {
+        self.shared.state.load()
+    }
+
+    pub fn toggle_client(&self) -> Result<ConnState, IllegalTransition> {
+        self.shared.apply(ConnEvent::Toggle)
+    }
+
+    pub fn toggle_sync(&self) -> Result<ConnState, IllegalTransition> {
+        self.shared.apply(ConnEvent::SyncRequest)
+    }
+
+    /// Stores a UI snapshot. In Native mode with an air geometry set, the
+    /// air byte comes from the tracked pointers and `air_byte` is ignored.
+    pub fn update_state(&self, update: &InputUpdate) {
+        let data = &self.shared.data;
+        data.packet_type.store(update.packet_type, Ordering::Relaxed);
+        data.air_mode.store(update.air_mode, Ordering::Relaxed);
+        // Held across the store, so a touch update cannot slip in between.
+        let tracker = self.shared.air.lock().ok().filter(|t| t.is_active() && update.air_mode == 1);
+        data.set_inputs(Inputs {
+            buttons: update.button_mask as u8,
+            air: tracker.as_ref().map_or(update.air_byte as u8, |t| t.air_byte()),
+            slider: update.slider_mask,
+        });
+        drop(tracker);
+
+        if update.packet_type == 48 {
+            if let Some(bcd) = update.card_bcd {
+                if let Ok(mut guard) = data.card_bcd.lock() {
+                    *guard = bcd;
+                }
+            }
+        }
+    }
+
+    pub fn set_mickey(&self, enabled: u32) {
+        self.shared.data.mickey.store(enabled, Ordering::Relaxed);
+    }
+
+    /// How a flick (and each Auto mode round) sweeps the air zones. Takes
+    /// effect from the next trigger; kept across restarts.
+    pub fn set_pulse_profile(&self, profile: PulseProfile) -> Result<(), ConfigError> {
+        profile.validate()?;
+        if let Ok(mut pulse) = self.shared.pulse.lock() {
+            pulse.set_profile(profile);
+        }
+        Ok(())
+    }
+
+    /// What a flick does while the previous flick's sweep is still running
+    /// (likewise for air-down); `Restart` by default. Kept across restarts.
+    pub fn set_retrigger_policy(&self, policy: RetriggerPolicy) {
+        if let Ok(mut pulse) = self.shared.pulse.lock() {
+            pulse.set_retrigger(policy);
+        }
+    }
+
+    pub fn pulse_profile(&self) -> PulseProfile {
+        self.shared.pulse.lock().map(|pulse| pulse.profile()).unwrap_or_default()
+    }
+
+    pub fn trigger_flick(&self) {
+        self.shared.data.pending_flicks.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Sweeps the air zones from the top down, for air-action notes; see
+    /// `pulse` for how it shares the pulse with flicks. Flick mode only.
+    pub fn trigger_air_down(&self) {
+        self.shared.data.pending_air_downs.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Where the air area is in touch coordinates; `None` leaves the air
+    /// byte to the UI again. Kept across restarts.
+    pub fn set_air_geometry(&self, geometry: Option<AirGeometry>) {
+        if let Ok(mut tracker) = self.shared.air.lock() {
+            tracker.set_geometry(geometry);
+        }
+    }
+
+    /// Recognises flicks natively from the touch stream (`None` = off, the
+    /// default). The UI should then stop calling `trigger_flick` itself.
+    /// Kept across restarts.
+    pub fn set_flick_detector(&self, config: Option<FlickConfig>) {
+        if let Ok(mut detector) = self.shared.flick.lock() {
+            detector.set_config(config);
+        }
+    }
+
+    /// Pointer `pid` went down at height `y` in the air geometry's terms.
+    pub fn touch_down(&self, pid: i32, y: f32) {
+        air::update_touch_down(&self.shared, pid, y);
+    }
+
+    pub fn touch_move(&self, pid: i32, y: f32) {
+        air::update_touch_move(&self.shared, pid, y);
+    }
+
+    pub fn touch_up(&self, pid: i32) {
+        air::update_touch_up(&self.shared, pid);
+    }
+}
+
+fn run(shared: Arc<Shared>) {
+    #[cfg(target_os = "android")]
+    unsafe {
+        let tid = libc::gettid();
+        let param = libc::sched_param { sched_priority: 99 };
+        libc::sched_setscheduler(tid, libc::SCHED_FIFO, &param);
+    }
+
+    let mut scheduler = TickScheduler::new(
+        shared.interval_ns.load(Ordering::Acquire),
+        MissPolicy::from_raw(shared.miss_policy.load(Ordering::Relaxed)),
+        timer::monotonic_ns(),
+    );
+    let mut next_flick_sample = timer::monotonic_ns();
+    let mut gate = DeltaGate::new();
+    let mut next_ping = 0;
+
+    while shared.running.load(Ordering::Acquire) {
+        // Read before the state, so a change racing with the idle check
+        // below still wakes us.
+        let generation = shared.waker.generation();
+        let current_state = shared.state.load();
+        let timer_config = shared.timer.load();
+        let now = timer::monotonic_ns();
+        scheduler.set_interval(shared.interval_ns.load(Ordering::Acquire), now);
+        scheduler.set_policy(MissPolicy::from_raw(shared.miss_policy.load(Ordering::Relaxed)));
+        let target_addr = *shared.target_addr.read().unwrap();
+
+        if shared.protocol.load(Ordering::Relaxed) == 1 {
+            if let Some(addr) = target_addr {
+                let is_connected = shared.tcp.stream
+                    .lock()
+                    .map(|g| g.is_some())
+                    .unwrap_or(false);
+                if !is_connected {
+                    connect_tcp(&shared, addr);
+                    shared.waker.wait_changed_timeout(generation, Duration::from_millis(500));
+                    scheduler.reset(timer::monotonic_ns());
+                    gate.reset();
+                    shared.telemetry.break_interval();
+                    continue;
+                }
+
+                if let Some(socket) = shared.socket.read().unwrap().as_ref() {
+                    maybe_hello(&shared, socket, &addr, now);
+                    maybe_ping(&shared, socket, &addr, &mut next_ping, now);
+                }
+
+                if timer_config.block_when_idle && current_state == ConnState::Idle {
+                    // Pongs arrive on the RX thread; no need to poll for them.
+                    park_idle(&shared, generation, next_ping, false);
+                    scheduler.reset(timer::monotonic_ns());
+                    gate.reset();
+                    shared.telemetry.break_interval();
+                    continue;
+                }
+
+                if now >= next_flick_sample {
+                    next_flick_sample = now + FLICK_INTERVAL_NS;
+                    air::process_flick_sampling(&shared);
+                }
+
+                let dummy_socket = shared.socket.read().unwrap();
+                if let Some(socket) = dummy_socket.as_ref() {
+                    delivery::handle_receive(&shared, socket, current_state);
+                }
+
+                if let ConnState::Syncing { .. } = current_state {
+                    delivery::handle_sync_timeout(&shared);
+                }
+
+                if let Some(missed) = scheduler.poll(now) {
+                    shared.telemetry.record_missed(missed);
+                    if let Some(socket) = dummy_socket.as_ref() {
+                        let outcome = delivery::send_packet(&shared, socket, &addr, current_state, &mut gate, now);
+                        record_outcome(&shared, outcome, now, scheduler.interval_ns());
+                    }
+                }
+            } else {
+                shared.waker.wait_changed_timeout(generation, Duration::from_millis(50));
+                scheduler.reset(timer::monotonic_ns());
+                gate.reset();
+                shared.telemetry.break_interval();
+                continue;
+            }
+        } else {
+            let socket_opt = shared.socket.read().unwrap();
+            if let (Some(addr), Some(socket)) = (target_addr, socket_opt.as_ref()) {
+                maybe_hello(&shared, socket, &addr, now);
+                maybe_ping(&shared, socket, &addr, &mut next_ping, now);
+
+                if timer_config.block_when_idle && current_state == ConnState::Idle {
+                    delivery::handle_receive(&shared, socket, current_state);
+                    drop(socket_opt);
+                    park_idle(&shared, generation, next_ping, true);
+                    scheduler.reset(timer::monotonic_ns());
+                    gate.reset();
+                    shared.telemetry.break_interval();
+                    continue;
+                }
+
+                if now >= next_flick_sample {
+                    next_flick_sample = now + FLICK_INTERVAL_NS;
+                    air::process_flick_sampling(&shared);
+                }
+
+                delivery::handle_receive(&shared, socket, current_state);
+
+                if let ConnState::Syncing { .. } = current_state {
+                    delivery::handle_sync_timeout(&shared);
+                }
+
+                if let Some(missed) = scheduler.poll(now) {
+                    shared.telemetry.record_missed(missed);
+                    let outcome = delivery::send_packet(&shared, socket, &addr, current_state, &mut gate, now);
+                    record_outcome(&shared, outcome, now, scheduler.interval_ns());
+                }
+            } else {
+                drop(socket_opt);
+                shared.waker.wait_changed_timeout(generation, Duration::from_millis(50));
+                scheduler.reset(timer::monotonic_ns());
+                gate.reset();
+                shared.telemetry.break_interval();
+                continue;
+            }
+        }
+
+        let mut deadline = scheduler.next_deadline().min(next_flick_sample);
+        if next_ping != 0 {
+            deadline = deadline.min(next_ping);
+        }
+        if shared.handshake.pending() {
+            deadline = deadline.min(shared.handshake.next_hello_ns());
+        }
+        timer::wait_until(timer_config.strategy, deadline);
+    }
+}
+
+fn maybe_hello(shared: &Shared, socket: &UdpSocket, addr: &SocketAddr, now: u64) {
+    if let Some(hello) = shared.handshake.poll(now, shared.pairing.nonce()) {
+        delivery::send_control(shared, socket, addr, hello);
+    }
+}
+
+/// Sends a ping if one is due. `next_ping` is 0 while pinging is off (or
+/// not agreed), so turning it on pings right away.
+fn maybe_ping(shared: &Shared, socket: &UdpSocket, addr: &SocketAddr, next_ping: &mut u64, now: u64) {
+    let interval = shared.ping_interval_ns.load(Ordering::Relaxed);
+    if interval == 0 || !shared.handshake.uses(Features::PING) {
+        *next_ping = 0;
+        return;
+    }
+    if now >= *next_ping {
+        *next_ping = now + interval;
+        delivery::send_ping(shared, socket, addr);
+    }
+}
+
+/// Blocks the idle loop until something changes. It also wakes for the next
+/// ping or hello and, if `poll_replies`, every millisecond while a pong or
+/// hello ack is outstanding, since UDP replies are only read by this thread.
+fn park_idle(shared: &Shared, generation: u64, next_ping: u64, poll_replies: bool) {
+    let hello_pending = shared.handshake.pending();
+    let mut until = if next_ping == 0 { u64::MAX } else { next_ping };
+    if hello_pending {
+        until = until.min(shared.handshake.next_hello_ns());
+    }
+    if until == u64::MAX {
+        shared.waker.wait_changed(generation);
+        return;
+    }
+    let now = timer::monotonic_ns();
+    if poll_replies && (hello_pending || shared.rtt.awaiting_pong()) {
+        until = until.min(now + 1_000_000);
+    }
+    shared.waker.wait_changed_timeout(generation, Duration::from_nanos(until.saturating_sub(now)));
+}
+
+fn record_outcome(shared: &Shared, outcome: SendOutcome, now: u64, interval_ns: u64) {
+    match outcome {
+        SendOutcome::Sent => shared.telemetry.record_send(now, interval_ns),
+        SendOutcome::Failed => shared.telemetry.record_error(),
+        SendOutcome::Skipped => shared.telemetry.break_interval(),
+    }
+}
+
+fn connect_tcp(shared: &Arc<Shared>, addr: SocketAddr) -> bool {
+    match TcpStream::connect_timeout(&addr, Duration::from_secs(3)) {
+        Ok(stream) => {
+            // Nagle off: 2-11 byte frames must go out immediately, not wait for ACK.
+            let _ = stream.set_nodelay(true);
+            // Non-blocking reads live in the dedicated RX thread (delivery.rs),
+            // so they can never throttle the send loop.
+            let _ = stream.set_nonblocking(true);
+            delivery::set_tcp_stream(shared, Some(stream));
+            // A new connection may lead to a different server.
+            shared.restart_handshake();
+            true
+        }
+        Err(_) => {
+            delivery::set_tcp_stream(shared, None);
+            false
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! Hello / hello-ack exchange.
+//!
+//! After every (re)configure and TCP connect the engine sends a hello with
+//! its version and offered features, retrying a few times. Features are
+//! used only once the server acknowledged them; a server that never answers
+//! is treated as a legacy one and gets the plain protocol. For a server
+//! without the exchange at all (`Dialect::Brokenithm`) a fixed feature set
+//! is assumed instead and no hello is sent.
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use crate::protocol::{ClientPacket, Features, PROTOCOL_VERSION};
+
+/// Hellos sent before giving up on the server.
+const MAX_HELLOS: u32 = 5;
+const HELLO_RETRY_NS: u64 = 200_000_000;
+
+pub(crate) struct Handshake {
+    offered: AtomicU32,
+    /// Server version + 1 once acknowledged, 0 while pending or legacy.
+    peer_version: AtomicU32,
+    agreed: AtomicU32,
+    hellos_sent: AtomicU32,
+    next_hello_ns: AtomicU64,
+    /// Features assumed without an exchange; `NO_FIXED` = negotiate.
+    fixed: AtomicU32,
+}
+
+const NO_FIXED: u32 = u32::MAX;
+
+impl Handshake {
+    pub fn new() -> Self {
+        Self {
+            offered: AtomicU32::new(Features::ALL.0 as u32),
+            peer_version: AtomicU32::new(0),
+            agreed: AtomicU32::new(0),
+            hellos_sent: AtomicU32::new(0),
+            next_hello_ns: AtomicU64::new(0),
+            fixed: AtomicU32::new(NO_FIXED),
+        }
+    }
+
+    /// Skips the exchange and uses `features` (as far as offered) from now
+    /// on; `None` goes back to negotiating.
+    pub fn set_fixed(&self, features: Option<Features>) {
+        self.fixed.store(features.map_or(NO_FIXED, |f| f.0 as u32), Ordering::Relaxed);
+        self.restart();
+    }
+
+    pub fn set_offered(&self, features: Features) {
+        self.offered.store((features & Features::ALL).0 as u32, Ordering::Relaxed);
+        self.restart();
+    }
+
+    pub fn offered(&self) -> Features {
+        Features(self.offered.load(Ordering::Relaxed) as u16)
+    }
+
+    /// Forgets the agreement and starts over with the next `poll`.
+    pub fn restart(&self) {
+        self.peer_version.store(0, Ordering::Release);
+        self.next_hello_ns.store(0, Ordering::Relaxed);
+        match self.fixed.load(Ordering::Relaxed) {
+            NO_FIXED => {
+                self.agreed.store(0, Ordering::Release);
+                self.hellos_sent.store(0, Ordering::Relaxed);
+            }
+            fixed => {
+                self.agreed.store((self.offered() & Features(fixed as u16)).0 as u32, Ordering::Release);
+                self.hellos_sent.store(MAX_HELLOS, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// The hello to send at `now_ns`, if one is due, announcing the pairing
+    /// session `nonce`.
+    pub fn poll(&self, now_ns: u64, nonce: u64) -> Option<ClientPacket> {
+        if !self.pending() || now_ns < self.next_hello_ns.load(Ordering::Relaxed) {
+            return None;
+        }
+        self.hellos_sent.fetch_add(1, Ordering::Relaxed);
+        self.next_hello_ns.store(now_ns + HELLO_RETRY_NS, Ordering::Relaxed);
+        Some(ClientPacket::Hello { version: PROTOCOL_VERSION, features: self.offered(), nonce })
+    }
+
+    /// Still waiting for an ack, with retries left.
+    pub fn pending(&self) -> bool {
+        self.peer_version.load(Ordering::Acquire) == 0
+            && self.hellos_sent.load(Ordering::Relaxed) < MAX_HELLOS
+    }
+
+    /// When `poll` will next return a hello, while `pending`.
+    pub fn next_hello_ns(&self) -> u64 {
+        self.next_hello_ns.load(Ordering::Relaxed)
+    }
+
+    pub fn on_ack(&self, version: u8, features: Features) {
+        self.agreed.store((self.offered() & features).0 as u32, Ordering::Release);
+        self.peer_version.store(version as u32 + 1, Ordering::Release);
+    }
+
+    pub fn uses(&self, feature: Features) -> bool {
+        Features(self.agreed.load(Ordering::Acquire) as u16).contains(feature)
+    }
+
+    /// Server version and agreed features, once the server answered.
+    pub fn negotiated(&self) -> Option<(u8, Features)> {
+        match self.peer_version.load(Ordering::Acquire) {
+            0 => None,
+            v => Some(((v - 1) as u8, Features(self.agreed.load(Ordering::Acquire) as u16))),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! Latest LED colours from the server.
+//!
+//! Double-buffered: the receiving thread fills the back slot and then flips
+//! `front`, so a reader only ever locks the slot nobody is writing. The
+//! version lets the UI skip frames it has already drawn.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use crate::protocol::LedFrame;
+
+pub(crate) struct LedBuffer {
+    slots: [Mutex<LedFrame>; 2],
+    front: AtomicUsize,
+    /// Serialises writers; the UDP path and a TCP RX thread on its way out
+    /// may overlap for a moment.
+    writer: Mutex<()>,
+    /// Number of frames published; 0 = none yet.
+    version: AtomicU64,
+}
+
+impl LedBuffer {
+    pub fn new() -> Self {
+        Self {
+            slots: [Mutex::new(LedFrame::default()), Mutex::new(LedFrame::default())],
+            front: AtomicUsize::new(0),
+            writer: Mutex::new(()),
+            version: AtomicU64::new(0),
+        }
+    }
+
+    pub fn publish(&self, frame: &LedFrame) {
+        let Ok(_writer) = self.writer.lock() else { return };
+        let back = 1 - self.front.load(Ordering::Acquire);
+        if let Ok(mut slot) = self.slots[back].lock() {
+            *slot = *frame;
+        }
+        self.front.store(back, Ordering::Release);
+        self.version.fetch_add(1, Ordering::AcqRel);
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+
+    /// The newest frame and its version.
+    pub fn read(&self) -> (u64, LedFrame) {
+        let version = self.version();
+        let front = self.front.load(Ordering::Acquire);
+        let frame = self.slots[front].lock().map(|f| *f).unwrap_or_default();
+        (version, frame)
+    }
+
+    /// Back to "no frame yet", for a restarted engine.
+    pub fn clear(&self) {
+        let Ok(_writer) = self.writer.lock() else { return };
+        for slot in &self.slots {
+            if let Ok(mut slot) = slot.lock() {
+                *slot = LedFrame::default();
+            }
+        }
+        self.version.store(0, Ordering::Release);
+    }
+}
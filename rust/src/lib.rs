@@ -1,266 +1,396 @@
-mod air;
-mod pulse;
 mod delivery;
+mod engine;
+mod handshake;
+mod led;
+mod state;
+pub mod config;
+pub mod protocol;
+pub mod loopback;
+pub mod scheduler;
+pub mod delta;
+pub mod timer;
+pub mod telemetry;
+pub mod rtt;
+pub mod auth;
+pub mod discovery;
+pub mod reliable;
+pub mod brokenithm;
+pub mod air;
+pub mod pulse;
+
+pub use config::{ConfigError, Dialect, Protocol};
+pub use engine::{Engine, InputUpdate};
+pub use state::{ConnEvent, ConnState, IllegalTransition};
 
 use jni::JNIEnv;
-use jni::objects::{JClass, JString};
-use jni::sys::{jint, jbyteArray};
-use std::net::{UdpSocket, SocketAddr, TcpStream};
-use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
-use std::sync::{Arc, Mutex, RwLock};
-use std::thread;
-use std::time::{Duration, Instant};
+use jni::objects::{JByteArray, JClass, JObject, JString};
+use jni::sys::{jboolean, jbyte, jfloat, jint, jlong, jlongArray, jobjectArray};
 use once_cell::sync::Lazy;
 
-pub(crate) static STATE_VALUE: AtomicU32 = AtomicU32::new(0);
-pub(crate) static PROTOCOL_TYPE: AtomicU32 = AtomicU32::new(0);
-pub(crate) static INTERVAL_NS: AtomicU64 = AtomicU64::new(1_000_000);
-
-pub(crate) static TARGET_ADDR: RwLock<Option<SocketAddr>> = RwLock::new(None);
-pub(crate) static SOCKET_HOLDER: RwLock<Option<UdpSocket>> = RwLock::new(None);
-
-pub(crate) struct NetData {
-    pub packet_type: AtomicU32,
-    pub button_mask: AtomicU32,
-    pub air_byte: AtomicU32,
-    pub slider_mask: AtomicU32,
-    pub handshake_storage: AtomicU32,
-    pub card_bcd: Mutex<[u8; 10]>,
-    pub sync_deadline: Mutex<Option<Instant>>,
-    pub sync_target_state: AtomicU32,
-    pub air_mode: AtomicU32,
-    pub mickey: AtomicU32,
-    pub flick_signal: AtomicU32,
-}
-
-pub(crate) static DATA_POOL: Lazy<Arc<NetData>> = Lazy::new(|| Arc::new(NetData {
-    packet_type: AtomicU32::new(16),
-    button_mask: AtomicU32::new(0),
-    air_byte: AtomicU32::new(0),
-    slider_mask: AtomicU32::new(0),
-    handshake_storage: AtomicU32::new(0),
-    card_bcd: Mutex::new([0u8; 10]),
-    sync_deadline: Mutex::new(None),
-    sync_target_state: AtomicU32::new(0),
-    air_mode: AtomicU32::new(1),
-    mickey: AtomicU32::new(0),
-    flick_signal: AtomicU32::new(0),
-}));
-
-fn start_permanent_loop() {
-    thread::Builder::new().name("RustNetEngine".into()).spawn(move || {
-        #[cfg(target_os = "android")]
-        unsafe {
-            let tid = libc::gettid();
-            let param = libc::sched_param { sched_priority: 99 };
-            libc::sched_setscheduler(tid, libc::SCHED_FIFO, &param);
-        }
+/// The single engine behind the `Net` JNI surface.
+static ENGINE: Lazy<Engine> = Lazy::new(Engine::new);
 
-        let mut last_tick = Instant::now();
-        let mut last_flick_sample = Instant::now();
-        let flick_interval = Duration::from_micros(1600);
-
-loop {
-    let current_state = STATE_VALUE.load(Ordering::Acquire);
-    let target_addr = TARGET_ADDR.read().unwrap().clone();
-
-    if PROTOCOL_TYPE.load(Ordering::Relaxed) == 1 {
-        if let Some(addr) = target_addr {
-            let is_connected = delivery::TCP_STREAM
-                .lock()
-                .map(|g| g.is_some())
-                .unwrap_or(false);
-            if !is_connected {
-                connect_tcp(addr);
-                thread::sleep(Duration::from_millis(500));
-                continue;
-            }
+#[no_mangle]
+pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeTouchDown(
+    _env: JNIEnv, _class: JClass, pid: jint, y: jint,
+) {
+    ENGINE.touch_down(pid, y as f32);
+}
 
-            if last_flick_sample.elapsed() >= flick_interval {
-                last_flick_sample = Instant::now();
-                air::process_flick_sampling();
-            }
+/// A height that is not positive turns native air tracking off.
+#[no_mangle]
+pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeSetAirGeometry(
+    _env: JNIEnv, _class: JClass, height: jfloat, pair_ratio: jfloat,
+) {
+    ENGINE.set_air_geometry(Some(air::AirGeometry { height, pair_ratio }));
+}
 
-            let dummy_socket = SOCKET_HOLDER.read().unwrap();
-            if let Some(socket) = dummy_socket.as_ref() {
-                delivery::handle_receive(socket, current_state);
-            }
+/// A distance that is not positive turns native flick recognition off.
+#[no_mangle]
+pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeSetFlickDetector(
+    _env: JNIEnv, _class: JClass,
+    min_distance: jfloat, min_velocity: jfloat, max_duration_ms: jint, air_down: jboolean,
+) {
+    let config = (min_distance > 0.0).then(|| air::FlickConfig {
+        min_distance,
+        min_velocity: min_velocity.max(0.0),
+        max_duration_ns: max_duration_ms.max(1) as u64 * 1_000_000,
+        air_down: air_down != 0,
+    });
+    ENGINE.set_flick_detector(config);
+}
 
-            if current_state == 2 {
-                delivery::handle_sync_timeout();
-            }
+/// `baseline` is a zone mask, bit 0 = zone 1. Throws on an invalid profile.
+#[no_mangle]
+pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeSetPulseProfile(
+    mut env: JNIEnv, _class: JClass, step_us: jint, order: jint, baseline: jint, sweeps: jint,
+) {
+    let out_of_range = |what: &str, value: jint| {
+        ConfigError::InvalidPulseProfile(format!("{what} {value} is out of range"))
+    };
+    let result = u32::try_from(order)
+        .map_err(|_| out_of_range("zone order", order))
+        .and_then(pulse::ZoneOrder::try_from)
+        .and_then(|order| {
+            let baseline = u8::try_from(baseline).map_err(|_| out_of_range("baseline", baseline))?;
+            ENGINE.set_pulse_profile(pulse::PulseProfile {
+                step_ns: step_us.max(0) as u64 * 1_000,
+                order,
+                baseline,
+                sweeps: sweeps.max(0) as u32,
+            })
+        });
+    if let Err(err) = result {
+        let _ = env.throw_new(config_exception_class(&err), err.to_string());
+    }
+}
 
-            let interval = Duration::from_nanos(INTERVAL_NS.load(Ordering::Acquire));
-            if last_tick.elapsed() >= interval {
-                last_tick = Instant::now();
-                let dummy_socket = SOCKET_HOLDER.read().unwrap();
-                if let Some(socket) = dummy_socket.as_ref() {
-                    delivery::send_packet(socket, &addr, current_state);
-                }
-            }
-        } else {
-            thread::sleep(Duration::from_millis(50));
+/// 0 = restart, 1 = queue, 2 = ignore; throws on anything else.
+#[no_mangle]
+pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeSetRetriggerPolicy(
+    mut env: JNIEnv, _class: JClass, policy: jint,
+) {
+    match pulse::RetriggerPolicy::try_from(policy as u32) {
+        Ok(policy) => ENGINE.set_retrigger_policy(policy),
+        Err(err) => {
+            let _ = env.throw_new(config_exception_class(&err), err.to_string());
         }
-    } else {
-        let socket_opt = SOCKET_HOLDER.read().unwrap();
-        if let (Some(addr), Some(socket)) = (target_addr, socket_opt.as_ref()) {
-            if last_flick_sample.elapsed() >= flick_interval {
-                last_flick_sample = Instant::now();
-                air::process_flick_sampling();
-            }
+    }
+}
 
-            delivery::handle_receive(socket, current_state);
+#[no_mangle]
+pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeTouchUp(
+    _env: JNIEnv, _class: JClass, pid: jint,
+) {
+    ENGINE.touch_up(pid);
+}
 
-            if current_state == 2 {
-                delivery::handle_sync_timeout();
-            }
+#[no_mangle]
+pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeUpdateFlickCoords(
+    _env: JNIEnv, _class: JClass, pid: jint, y: jint,
+) {
+    ENGINE.touch_move(pid, y as f32);
+}
 
-            let interval = Duration::from_nanos(INTERVAL_NS.load(Ordering::Acquire));
-            if last_tick.elapsed() >= interval {
-                last_tick = Instant::now();
-                delivery::send_packet(socket, &addr, current_state);
-            }
-        } else {
-            thread::sleep(Duration::from_millis(50));
-        }
+#[no_mangle]
+pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeTriggerFlick(
+    _env: JNIEnv, _class: JClass,
+) {
+    ENGINE.trigger_flick();
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeTriggerAirDown(
+    _env: JNIEnv, _class: JClass,
+) {
+    ENGINE.trigger_air_down();
+}
+
+#[no_mangle]
+pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeGetState(
+    _env: JNIEnv, _class: JClass,
+) -> jint {
+    ENGINE.state().code() as jint
+}
+
+/// Send-loop telemetry as a `long[]`; see `TelemetrySnapshot::to_array` for
+/// the field order. Returns null if the array cannot be allocated.
+#[no_mangle]
+pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeGetTelemetry(
+    env: JNIEnv, _class: JClass,
+) -> jlongArray {
+    let values = ENGINE.telemetry().to_array();
+    let Ok(array) = env.new_long_array(values.len() as i32) else {
+        return std::ptr::null_mut();
+    };
+    if env.set_long_array_region(&array, 0, &values).is_err() {
+        return std::ptr::null_mut();
     }
+    array.into_raw()
+}
 
-    std::hint::spin_loop();
+/// Copies the newest LED frame into `out` (`protocol::LED_FRAME_LEN` bytes,
+/// RGB, slider then air) unless its version is still `seen`. Returns the
+/// version, 0 while no frame has arrived.
+#[no_mangle]
+pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeReadLeds(
+    env: JNIEnv, _class: JClass, out: JByteArray, seen: jlong,
+) -> jlong {
+    let version = ENGINE.led_version();
+    if version == seen as u64 {
+        return seen;
+    }
+    let (version, frame) = ENGINE.leds();
+    let bytes = frame.to_bytes().map(|b| b as jbyte);
+    if env.set_byte_array_region(&out, 0, &bytes).is_err() {
+        return seen;
+    }
+    version as jlong
 }
-}).expect("Failed to spawn RustNetEngine");
+
+/// `mask` is a `protocol::Features` bit set.
+#[no_mangle]
+pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeSetFeatures(
+    _env: JNIEnv, _class: JClass, mask: jint,
+) {
+    ENGINE.set_features(protocol::Features(mask as u16));
 }
 
-fn connect_tcp(addr: SocketAddr) -> bool {
-    match TcpStream::connect_timeout(&addr, Duration::from_secs(3)) {
-        Ok(stream) => {
-            // Nagle off: 2-11 byte frames must go out immediately, not wait for ACK.
-            let _ = stream.set_nodelay(true);
-            // Non-blocking reads live in the dedicated RX thread (delivery.rs),
-            // so they can never throttle the send loop.
-            let _ = stream.set_nonblocking(true);
-            delivery::set_tcp_stream(Some(stream));
-            true
+/// `version << 16 | features` once the server answered the hello, else -1.
+#[no_mangle]
+pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeGetNegotiated(
+    _env: JNIEnv, _class: JClass,
+) -> jint {
+    match ENGINE.negotiated() {
+        Some((version, features)) => (version as jint) << 16 | features.0 as jint,
+        None => -1,
+    }
+}
+
+/// `keepalive_ms <= 0` sends on every tick; otherwise only on change, plus
+/// `repeats` copies and a keepalive every `keepalive_ms`.
+#[no_mangle]
+pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeSetSendMode(
+    _env: JNIEnv, _class: JClass, repeats: jint, keepalive_ms: jint,
+) {
+    let mode = if keepalive_ms <= 0 {
+        delta::SendMode::Continuous
+    } else {
+        delta::SendMode::Delta {
+            repeats: repeats.max(0) as u32,
+            keepalive_ns: keepalive_ms as u64 * 1_000_000,
         }
-        Err(_) => {
-            delivery::set_tcp_stream(None);
-            false
+    };
+    ENGINE.set_send_mode(mode);
+}
+
+/// `max_burst <= 0` skips missed send slots; otherwise up to `max_burst` of
+/// them are caught up back to back.
+#[no_mangle]
+pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeSetMissPolicy(
+    _env: JNIEnv, _class: JClass, max_burst: jint,
+) {
+    let policy = if max_burst <= 0 {
+        scheduler::MissPolicy::Skip
+    } else {
+        scheduler::MissPolicy::CatchUp { max_burst: max_burst as u32 }
+    };
+    ENGINE.set_miss_policy(policy);
+}
+
+/// `spin_margin_us < 0` busy-waits every interval; otherwise the engine
+/// sleeps until `spin_margin_us` before each deadline and spins the rest.
+#[no_mangle]
+pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeSetTimer(
+    _env: JNIEnv, _class: JClass, spin_margin_us: jint, block_when_idle: jboolean,
+) {
+    let strategy = if spin_margin_us < 0 {
+        timer::WaitStrategy::Spin
+    } else {
+        timer::WaitStrategy::Hybrid { spin_margin_ns: spin_margin_us as u64 * 1_000 }
+    };
+    ENGINE.set_timer(timer::TimerConfig { strategy, block_when_idle: block_when_idle != 0 });
+}
+
+/// RTT estimates as a `long[]`; see `RttSnapshot::to_array` for the field
+/// order. Returns null if the array cannot be allocated.
+#[no_mangle]
+pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeGetRtt(
+    env: JNIEnv, _class: JClass,
+) -> jlongArray {
+    let values = ENGINE.rtt().to_array();
+    let Ok(array) = env.new_long_array(values.len() as i32) else {
+        return std::ptr::null_mut();
+    };
+    if env.set_long_array_region(&array, 0, &values).is_err() {
+        return std::ptr::null_mut();
+    }
+    array.into_raw()
+}
+
+/// Probes the LAN for `timeout_ms` and returns one `host|port|transports|name`
+/// string per server, or null on failure. Blocks for the whole timeout.
+#[no_mangle]
+pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeDiscover(
+    mut env: JNIEnv, _class: JClass, timeout_ms: jint,
+) -> jobjectArray {
+    let timeout = std::time::Duration::from_millis(timeout_ms.max(0) as u64);
+    let Ok(servers) = discovery::discover(&[discovery::broadcast_target()], timeout) else {
+        return std::ptr::null_mut();
+    };
+    let Ok(array) = env.new_object_array(servers.len() as i32, "java/lang/String", JObject::null()) else {
+        return std::ptr::null_mut();
+    };
+    for (i, server) in servers.iter().enumerate() {
+        let line = format!(
+            "{}|{}|{}|{}",
+            server.addr.ip(), server.addr.port(), server.transports.0, server.name
+        );
+        let Ok(jline) = env.new_string(line) else {
+            return std::ptr::null_mut();
+        };
+        if env.set_object_array_element(&array, i as i32, &jline).is_err() {
+            return std::ptr::null_mut();
         }
     }
+    array.into_raw()
 }
 
+/// 0 = native framing, 1 = original Brokenithm packets. Throws
+/// `NetConfigException.UnsupportedProtocol` for anything else.
 #[no_mangle]
-pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeTouchDown(
-    _env: JNIEnv, _class: JClass, pid: jint, y: jint,
+pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeSetDialect(
+    mut env: JNIEnv, _class: JClass, dialect: jint,
 ) {
-    air::update_touch_down(pid, y as f32);
+    match Dialect::try_from(dialect as u32) {
+        Ok(dialect) => ENGINE.set_dialect(dialect),
+        Err(err) => {
+            let _ = env.throw_new(config_exception_class(&err), err.to_string());
+        }
+    }
 }
 
+/// Null or an empty array leaves pairing mode.
 #[no_mangle]
-pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeTouchUp(
-    _env: JNIEnv, _class: JClass, pid: jint,
+pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeSetPairingKey(
+    env: JNIEnv, _class: JClass, key: JByteArray,
 ) {
-    air::update_touch_up(pid);
+    let key = if key.is_null() { None } else { env.convert_byte_array(&key).ok() };
+    ENGINE.set_pairing_key(key.as_deref());
 }
 
+/// Frames dropped by pairing as a `long[]`; see `AuthStats::to_array` for the
+/// field order. Returns null if the array cannot be allocated.
 #[no_mangle]
-pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeUpdateFlickCoords(
-    _env: JNIEnv, _class: JClass, pid: jint, y: jint,
-) {
-    air::update_touch_move(pid, y as f32);
+pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeGetAuthStats(
+    env: JNIEnv, _class: JClass,
+) -> jlongArray {
+    let values = ENGINE.auth_stats().to_array();
+    let Ok(array) = env.new_long_array(values.len() as i32) else {
+        return std::ptr::null_mut();
+    };
+    if env.set_long_array_region(&array, 0, &values).is_err() {
+        return std::ptr::null_mut();
+    }
+    array.into_raw()
 }
 
+/// `interval_ms <= 0` stops pinging.
 #[no_mangle]
-pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeTriggerFlick(
-    _env: JNIEnv, _class: JClass,
+pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeSetPingInterval(
+    _env: JNIEnv, _class: JClass, interval_ms: jint,
 ) {
-    DATA_POOL.flick_signal.store(1, Ordering::SeqCst);
+    let interval = (interval_ms > 0).then(|| std::time::Duration::from_millis(interval_ms as u64));
+    ENGINE.set_ping_interval(interval);
 }
 
 #[no_mangle]
-pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeGetState(
+pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeResetTelemetry(
     _env: JNIEnv, _class: JClass,
-) -> jint {
-    STATE_VALUE.load(Ordering::Acquire) as jint
+) {
+    ENGINE.reset_telemetry();
 }
 
 #[no_mangle]
 pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeToggleClient(
     _env: JNIEnv, _class: JClass,
 ) {
-    let current = STATE_VALUE.load(Ordering::Acquire);
-    let next = if current == 1 { 0 } else { 1 };
-    if current == 2 {
-        // WAITING (sync in progress): the connect button means "disconnect".
-        // Cancel the sync instead of ignoring the tap, otherwise leaving the
-        // page mid-sync would never stop the engine.
-        if let Ok(mut guard) = DATA_POOL.sync_deadline.lock() {
-            *guard = None;
-        }
-        STATE_VALUE.store(0, Ordering::SeqCst);
-        return;
-    }
-    STATE_VALUE.store(next, Ordering::SeqCst);
+    // Illegal toggles are simply ignored; the UI polls the state anyway.
+    let _ = ENGINE.toggle_client();
 }
 
 #[no_mangle]
 pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeToggleSync(
     _env: JNIEnv, _class: JClass,
 ) {
-    let current = STATE_VALUE.load(Ordering::Acquire);
-    if current == 2 { return; }
-    let target = if current == 1 { 0 } else { 1 };
-    DATA_POOL.sync_target_state.store(target, Ordering::Relaxed);
-    if let Ok(mut guard) = DATA_POOL.sync_deadline.lock() {
-        *guard = Some(Instant::now() + Duration::from_millis(500));
-    }
-    STATE_VALUE.store(2, Ordering::SeqCst);
+    let _ = ENGINE.toggle_sync();
 }
 
 #[no_mangle]
 pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeInit(
     _env: JNIEnv, _class: JClass, freq: jint,
 ) {
-    let ns = 1_000_000_000 / (freq.max(1) as u64);
-    INTERVAL_NS.store(ns, Ordering::SeqCst);
+    ENGINE.start(freq.max(1) as u32);
+}
 
-    static ONCE: std::sync::Once = std::sync::Once::new();
-    ONCE.call_once(start_permanent_loop);
+/// Stops every engine thread and releases the sockets; `nativeInit` restarts.
+#[no_mangle]
+pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeShutdown(
+    _env: JNIEnv, _class: JClass,
+) {
+    ENGINE.shutdown();
 }
 
+/// Kotlin exception class for each `ConfigError`; all extend `NetConfigException`.
+fn config_exception_class(err: &ConfigError) -> &'static str {
+    match err {
+        ConfigError::InvalidAddress(_) => "org/cf0x/rustnithm/Data/NetConfigException$InvalidAddress",
+        ConfigError::UnresolvedHost(_) => "org/cf0x/rustnithm/Data/NetConfigException$UnresolvedHost",
+        ConfigError::Bind(_) => "org/cf0x/rustnithm/Data/NetConfigException$BindFailed",
+        ConfigError::UnsupportedProtocol(_) => "org/cf0x/rustnithm/Data/NetConfigException$UnsupportedProtocol",
+        ConfigError::NotInitialized => "org/cf0x/rustnithm/Data/NetConfigException$EngineNotInitialized",
+        ConfigError::InvalidPulseProfile(_) => "org/cf0x/rustnithm/Data/NetConfigException$InvalidPulseProfile",
+    }
+}
+
+/// Throws a `NetConfigException` subclass on failure; returns normally on success.
+/// Hostnames are resolved on the calling thread, so Kotlin calls this off
+/// the main thread.
 #[no_mangle]
 pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeUpdateConfig(
     mut env: JNIEnv, _class: JClass,
     ip: JString, port: jint, protocol_type: jint,
 ) {
-    let ip_str: String = match env.get_string(&ip) {
-        Ok(s) => s.into(),
-        Err(_) => return,
-    };
-    let addr: SocketAddr = match format!("{}:{}", ip_str, port).parse() {
-        Ok(a) => a,
-        Err(_) => return,
+    let result = match env.get_string(&ip) {
+        Ok(s) => {
+            let ip_str: String = s.into();
+            ENGINE.configure_host(&ip_str, port, protocol_type as u32).map(|_| ())
+        }
+        Err(_) => Err(ConfigError::InvalidAddress(String::new())),
     };
 
-        if let Ok(mut guard) = TARGET_ADDR.write() { *guard = Some(addr); }
-    PROTOCOL_TYPE.store(protocol_type as u32, Ordering::SeqCst);
-
-    match protocol_type {
-        1 => {
-                                                delivery::set_tcp_stream(None);
-            if let Ok(dummy) = UdpSocket::bind("0.0.0.0:0") {
-                let _ = dummy.set_nonblocking(true);
-                if let Ok(mut guard) = SOCKET_HOLDER.write() { *guard = Some(dummy); }
-            }
-            // No connect here: the engine loop owns reconnection and will pick
-            // up TARGET_ADDR on its next pass (within ~50 ms).
-        }
-        _ => {
-                        delivery::set_tcp_stream(None);
-            if let Ok(socket) = UdpSocket::bind("0.0.0.0:0") {
-                let _ = socket.set_nonblocking(true);
-                if let Ok(mut guard) = SOCKET_HOLDER.write() { *guard = Some(socket); }
-            }
-        }
+    if let Err(err) = result {
+        let _ = env.throw_new(config_exception_class(&err), err.to_string());
     }
 }
 
@@ -268,32 +398,31 @@ pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeUpdateConfig(
 pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeMickeyButton(
     _env: JNIEnv, _class: JClass, enabled: jint,
 ) {
-    DATA_POOL.mickey.store(enabled as u32, Ordering::Relaxed);
+    ENGINE.set_mickey(enabled as u32);
 }
 
 #[no_mangle]
 pub extern "system" fn Java_org_cf0x_rustnithm_Data_Net_nativeUpdateState(
     env: JNIEnv, _class: JClass,
-    packet_type: jint, button_mask: jint, _air_byte: jint,
-    slider_mask: jint, handshake_payload: jint,
-    card_bcd: jbyteArray, air_mode: jint,
+    packet_type: jint, button_mask: jint, air_byte: jint,
+    slider_mask: jint,
+    card_bcd: JByteArray, air_mode: jint,
 ) {
-    let data = &*DATA_POOL;
-    data.packet_type.store(packet_type as u32, Ordering::Relaxed);
-    data.button_mask.store(button_mask as u32, Ordering::Relaxed);
-    data.air_byte.store(_air_byte as u32, Ordering::Relaxed);
-    data.slider_mask.store(slider_mask as u32, Ordering::Relaxed);
-    data.handshake_storage.store(handshake_payload as u32, Ordering::Relaxed);
-    data.air_mode.store(air_mode as u32, Ordering::Relaxed);
-
+    let mut card = None;
     if packet_type == 48 && !card_bcd.is_null() {
-        let array_obj = unsafe { jni::objects::JByteArray::from_raw(card_bcd) };
-        if let Ok(bytes) = env.convert_byte_array(&array_obj) {
-            if bytes.len() == 10 {
-                if let Ok(mut guard) = data.card_bcd.lock() {
-                    guard.copy_from_slice(&bytes);
-                }
+        if let Ok(bytes) = env.convert_byte_array(&card_bcd) {
+            if let Ok(bcd) = <[u8; 10]>::try_from(bytes.as_slice()) {
+                card = Some(bcd);
             }
         }
     }
-}
\ No newline at end of file
+
+    ENGINE.update_state(&InputUpdate {
+        packet_type: packet_type as u32,
+        button_mask: button_mask as u32,
+        air_byte: air_byte as u32,
+        slider_mask: slider_mask as u32,
+        air_mode: air_mode as u32,
+        card_bcd: card,
+    });
+}
@@ -0,0 +1,439 @@
+//! A stand-in for Rustnithm-Server: listens on UDP and TCP on the same port,
+//! decodes client frames, answers sync requests and keeps the latest inputs
+//! for inspection. LED frames set with `send_leds` are pushed to every
+//! client. With `set_pairing_key` it only accepts sealed frames and seals its
+//! replies, and with `set_dialect` it plays a Brokenithm server. Used by the `rustnithm-loopback` binary and by tests.
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use crate::auth::{Direction, Pairing, MAX_SEALED};
+use crate::brokenithm::{self, ClientFrame};
+use crate::config::Dialect;
+use crate::protocol::{self, ClientPacket, Features, LedFrame, ServerPacket, Stamp, Transport, PROTOCOL_VERSION};
+use crate::reliable::DedupeWindow;
+
+/// How often blocked reads wake up to check for shutdown.
+const POLL: Duration = Duration::from_millis(20);
+/// Header bits 5-4: packet type, 00 for sync.
+const TYPE_MASK: u8 = 0x30;
+
+/// The most recent inputs seen from any client.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InputView {
+    pub connected: bool,
+    pub buttons: u8,
+    pub air: u8,
+    pub slider: u32,
+    pub card: Option<[u8; 10]>,
+    pub packets: u64,
+    pub bad_frames: u64,
+    pub last_transport: Option<Transport>,
+    /// Packets that carried a `Stamp`.
+    pub stamped: u64,
+    /// Sequence numbers skipped over, i.e. packets presumed lost.
+    pub lost: u64,
+    /// Stamped packets that were duplicates or arrived after a newer one.
+    pub reordered: u64,
+    pub last_stamp: Option<Stamp>,
+    /// Version and features of the last hello.
+    pub hello: Option<(u8, Features)>,
+    /// Frames dropped by pairing: not authentic, or replays.
+    pub rejected: u64,
+    /// Reliable messages applied, and retransmissions of them only re-acked.
+    pub messages: u64,
+    pub duplicates: u64,
+    /// Coin presses; only Brokenithm signals them separately.
+    pub coins: u64,
+    /// Frames with the sync type bits, which is all a server that predates
+    /// the handshake looks at to take a frame for a sync.
+    pub sync_frames: u64,
+}
+
+impl InputView {
+    fn record_stamp(&mut self, stamp: Stamp) {
+        self.stamped += 1;
+        if let Some(last) = self.last_stamp {
+            let ahead = stamp.seq.wrapping_sub(last.seq);
+            if ahead == 0 || ahead >= 0x8000 {
+                self.reordered += 1;
+                return;
+            }
+            self.lost += u64::from(ahead - 1);
+        }
+        self.last_stamp = Some(stamp);
+    }
+}
+
+struct ServerShared {
+    view: Mutex<InputView>,
+    /// LED frame to push and its version; 0 = nothing to push yet.
+    leds: Mutex<(u64, LedFrame)>,
+    /// Features acknowledged in hellos; `None` ignores hellos like an old server.
+    features: Mutex<Option<Features>>,
+    pairing: Pairing,
+    dedupe: Mutex<DedupeWindow>,
+    /// Reliable messages still to be lost on arrival, and acks still to be
+    /// lost on the way out, to exercise retransmission.
+    lose_messages: AtomicU32,
+    lose_acks: AtomicU32,
+    /// Sync confirms still to be lost on the way out.
+    lose_confirms: AtomicU32,
+    /// Speaks `Dialect::Brokenithm` instead of the native framing.
+    brokenithm: AtomicBool,
+    stop: AtomicBool,
+}
+
+/// Takes one from `counter` unless it is already 0.
+fn take_one(counter: &AtomicU32) -> bool {
+    counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1)).is_ok()
+}
+
+impl ServerShared {
+    /// The LED frame if it is newer than `sent`, which is then updated.
+    fn leds_since(&self, sent: &mut u64) -> Option<ServerPacket> {
+        let (version, frame) = *self.leds.lock().ok()?;
+        if version == *sent {
+            return None;
+        }
+        *sent = version;
+        Some(ServerPacket::Led(frame))
+    }
+
+    /// `packet` as it goes on the wire, sealed while paired; empty if the
+    /// dialect has no such packet.
+    fn wire(&self, packet: ServerPacket, transport: Transport) -> Vec<u8> {
+        if self.brokenithm.load(Ordering::Relaxed) {
+            return brokenithm::encode_server(&packet).unwrap_or_default();
+        }
+        self.pairing.protect(packet.encode(transport).as_bytes()).as_bytes().to_vec()
+    }
+
+    /// Appends `packet` to a TCP stream buffer.
+    fn write_tcp(&self, packet: ServerPacket, out: &mut Vec<u8>) {
+        let wire = self.wire(packet, Transport::Tcp);
+        if wire.is_empty() {
+            return;
+        }
+        if self.brokenithm.load(Ordering::Relaxed) {
+            // Brokenithm frames carry their own size byte.
+            out.extend_from_slice(&wire);
+        } else {
+            protocol::write_tcp_frame(&wire, out);
+        }
+    }
+
+    fn split_tcp<'a>(&self, buf: &'a [u8]) -> Option<(&'a [u8], usize)> {
+        if self.brokenithm.load(Ordering::Relaxed) {
+            brokenithm::split_frame(buf)
+        } else {
+            protocol::split_tcp_frame(buf)
+        }
+    }
+
+    /// Applies one client frame and returns the replies, acks first.
+    /// `transport` is only needed for Brokenithm frames, which lack the bit.
+    fn handle(&self, frame: &[u8], transport: Transport) -> Vec<ServerPacket> {
+        if self.brokenithm.load(Ordering::Relaxed) {
+            return self.handle_brokenithm(frame, transport).into_iter().collect();
+        }
+        let Ok(mut view) = self.view.lock() else { return Vec::new() };
+        let Some(frame) = self.pairing.accept(frame) else {
+            view.rejected += 1;
+            return Vec::new();
+        };
+        if frame.as_bytes().first().is_some_and(|header| header & TYPE_MASK == 0) {
+            view.sync_frames += 1;
+        }
+        let (packet, transport, id, stamp) = match ClientPacket::decode_message(frame.as_bytes()) {
+            Ok(decoded) => decoded,
+            Err(_) => {
+                view.bad_frames += 1;
+                return Vec::new();
+            }
+        };
+        if id.is_some() && take_one(&self.lose_messages) {
+            return Vec::new();
+        }
+        view.packets += 1;
+        view.last_transport = Some(transport);
+        if let Some(stamp) = stamp {
+            view.record_stamp(stamp);
+        }
+
+        let mut replies = Vec::new();
+        if let Some(id) = id {
+            if !take_one(&self.lose_acks) {
+                replies.push(ServerPacket::Ack { id });
+            }
+            let fresh = self.dedupe.lock().map(|mut d| d.accept(id)).unwrap_or(true);
+            if !fresh {
+                view.duplicates += 1;
+                // The confirm of the original may have been lost; only the
+                // ack is reliable.
+                if let ClientPacket::Sync { .. } = packet {
+                    replies.push(ServerPacket::SyncConfirm { connected: view.connected });
+                }
+                return self.drop_lost_confirms(replies);
+            }
+            view.messages += 1;
+        }
+        replies.extend(self.apply(&mut view, packet));
+        self.drop_lost_confirms(replies)
+    }
+
+    fn drop_lost_confirms(&self, mut replies: Vec<ServerPacket>) -> Vec<ServerPacket> {
+        replies.retain(|reply| {
+            !matches!(reply, ServerPacket::SyncConfirm { .. }) || !take_one(&self.lose_confirms)
+        });
+        replies
+    }
+
+    fn handle_brokenithm(&self, frame: &[u8], transport: Transport) -> Option<ServerPacket> {
+        let mut view = self.view.lock().ok()?;
+        let Ok(decoded) = brokenithm::decode_client(frame) else {
+            view.bad_frames += 1;
+            return None;
+        };
+        view.packets += 1;
+        view.last_transport = Some(transport);
+        match decoded {
+            ClientFrame::Input { air, slider, test, service } => {
+                // No sync: a client is on as soon as it sends input.
+                view.connected = true;
+                view.air = air;
+                view.slider = slider;
+                view.buttons = (u8::from(test) << 2) | (u8::from(service) << 1);
+            }
+            ClientFrame::Function(brokenithm::FUNCTION_COIN) => view.coins += 1,
+            ClientFrame::Function(_) => {}
+            ClientFrame::Card(card) => view.card = card,
+            ClientFrame::Ping(value) => {
+                return Some(ServerPacket::Pong { id: (value >> 32) as u16, micros: value as u32 });
+            }
+        }
+        None
+    }
+
+    /// Updates the view for one new packet and returns the reply, if any.
+    fn apply(&self, view: &mut InputView, packet: ClientPacket) -> Option<ServerPacket> {
+        match packet {
+            ClientPacket::Sync { target_connected } => {
+                view.connected = target_connected;
+                return Some(ServerPacket::SyncConfirm { connected: target_connected });
+            }
+            ClientPacket::Buttons(mask) => view.buttons = mask,
+            ClientPacket::AirSlider { air, slider } => {
+                view.air = air;
+                view.slider = slider;
+            }
+            ClientPacket::FullState { buttons, air, slider } => {
+                view.buttons = buttons;
+                view.air = air;
+                view.slider = slider;
+            }
+            ClientPacket::Ping { id, micros } => return Some(ServerPacket::Pong { id, micros }),
+            ClientPacket::Hello { version, features, nonce } => {
+                view.hello = Some((version, features));
+                // A new session: its message ids start over.
+                if let Ok(mut dedupe) = self.dedupe.lock() {
+                    dedupe.clear();
+                }
+                let ours = (*self.features.lock().ok()?)?;
+                self.pairing.set_peer_nonce(nonce);
+                return Some(ServerPacket::HelloAck {
+                    version: PROTOCOL_VERSION,
+                    features: ours,
+                    nonce: self.pairing.nonce(),
+                });
+            }
+            ClientPacket::Card(bcd) => view.card = Some(bcd),
+        }
+        None
+    }
+}
+
+pub struct LoopbackServer {
+    addr: SocketAddr,
+    shared: Arc<ServerShared>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl LoopbackServer {
+    /// Binds TCP on `addr` and UDP on the same resolved port, so port 0 works.
+    pub fn bind(addr: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let udp = UdpSocket::bind(addr)?;
+        udp.set_read_timeout(Some(POLL))?;
+        listener.set_nonblocking(true)?;
+
+        let shared = Arc::new(ServerShared {
+            view: Mutex::new(InputView::default()),
+            leds: Mutex::new((0, LedFrame::default())),
+            features: Mutex::new(Some(Features::ALL)),
+            pairing: Pairing::new(Direction::ToClient),
+            dedupe: Mutex::new(DedupeWindow::default()),
+            lose_messages: AtomicU32::new(0),
+            lose_acks: AtomicU32::new(0),
+            lose_confirms: AtomicU32::new(0),
+            brokenithm: AtomicBool::new(false),
+            stop: AtomicBool::new(false),
+        });
+
+        let udp_shared = Arc::clone(&shared);
+        let tcp_shared = Arc::clone(&shared);
+        let threads = vec![
+            thread::Builder::new()
+                .name("LoopbackUdp".into())
+                .spawn(move || udp_loop(udp, udp_shared))?,
+            thread::Builder::new()
+                .name("LoopbackTcp".into())
+                .spawn(move || accept_loop(listener, tcp_shared))?,
+        ];
+
+        Ok(Self { addr, shared, threads })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn snapshot(&self) -> InputView {
+        self.shared.view.lock().map(|v| *v).unwrap_or_default()
+    }
+
+    /// Features to acknowledge in hellos (all by default). `None` makes the
+    /// server ignore hellos, like one that predates the handshake.
+    pub fn set_features(&self, features: Option<Features>) {
+        if let Ok(mut guard) = self.shared.features.lock() {
+            *guard = features;
+        }
+    }
+
+    /// Shared key for pairing mode; `None` accepts and sends plain frames.
+    pub fn set_pairing_key(&self, key: Option<&[u8]>) {
+        let _ = self.shared.pairing.set_key(key.map(<[u8]>::to_vec));
+    }
+
+    /// Wire format to speak. A Brokenithm server ignores hellos and pairing
+    /// and pushes LED frames in its own format.
+    pub fn set_dialect(&self, dialect: Dialect) {
+        self.shared.brokenithm.store(dialect == Dialect::Brokenithm, Ordering::Relaxed);
+    }
+
+    /// Loses the next `count` reliable messages on arrival, unacked.
+    pub fn lose_messages(&self, count: u32) {
+        self.shared.lose_messages.store(count, Ordering::Relaxed);
+    }
+
+    /// Applies the next `count` reliable messages but loses their acks.
+    pub fn lose_acks(&self, count: u32) {
+        self.shared.lose_acks.store(count, Ordering::Relaxed);
+    }
+
+    /// Loses the next `count` sync confirms on the way out.
+    pub fn lose_confirms(&self, count: u32) {
+        self.shared.lose_confirms.store(count, Ordering::Relaxed);
+    }
+
+    /// Pushes `frame` to every client, once each, as soon as it is next heard
+    /// from (UDP) or within one poll period (TCP).
+    pub fn send_leds(&self, frame: LedFrame) {
+        if let Ok(mut leds) = self.shared.leds.lock() {
+            *leds = (leds.0 + 1, frame);
+        }
+    }
+}
+
+impl Drop for LoopbackServer {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::SeqCst);
+        for handle in self.threads.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn udp_loop(socket: UdpSocket, shared: Arc<ServerShared>) {
+    let mut buf = [0u8; 64];
+    let mut sent_leds = 0;
+    while !shared.stop.load(Ordering::Relaxed) {
+        if let Ok((size, peer)) = socket.recv_from(&mut buf) {
+            let mut replies = shared.handle(&buf[..size], Transport::Udp);
+            replies.extend(shared.leds_since(&mut sent_leds));
+            for reply in replies {
+                let wire = shared.wire(reply, Transport::Udp);
+                if !wire.is_empty() {
+                    let _ = socket.send_to(&wire, peer);
+                }
+            }
+        }
+    }
+}
+
+fn accept_loop(listener: TcpListener, shared: Arc<ServerShared>) {
+    let mut clients = Vec::new();
+    while !shared.stop.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let client_shared = Arc::clone(&shared);
+                if let Ok(handle) = thread::Builder::new()
+                    .name("LoopbackTcpClient".into())
+                    .spawn(move || tcp_client_loop(stream, client_shared))
+                {
+                    clients.push(handle);
+                }
+            }
+            Err(_) => thread::sleep(POLL),
+        }
+    }
+    for handle in clients {
+        let _ = handle.join();
+    }
+}
+
+fn tcp_client_loop(mut stream: TcpStream, shared: Arc<ServerShared>) {
+    if stream.set_nonblocking(false).is_err() || stream.set_read_timeout(Some(POLL)).is_err() {
+        return;
+    }
+    let _ = stream.set_nodelay(true);
+
+    let mut tmp = [0u8; 256];
+    let mut buf = Vec::new();
+    let mut sent_leds = 0;
+    while !shared.stop.load(Ordering::Relaxed) {
+        if let Some(leds) = shared.leds_since(&mut sent_leds) {
+            let mut out = Vec::with_capacity(2 + MAX_SEALED);
+            shared.write_tcp(leds, &mut out);
+            if stream.write_all(&out).is_err() {
+                return;
+            }
+        }
+        match stream.read(&mut tmp) {
+            Ok(0) => return,
+            Ok(n) => buf.extend_from_slice(&tmp[..n]),
+            Err(ref e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                continue
+            }
+            Err(_) => return,
+        }
+
+        while let Some((frame, consumed)) = shared.split_tcp(&buf) {
+            let replies = shared.handle(frame, Transport::Tcp);
+            buf.drain(..consumed);
+            let mut out = Vec::with_capacity(4);
+            for reply in replies {
+                shared.write_tcp(reply, &mut out);
+            }
+            if !out.is_empty() && stream.write_all(&out).is_err() {
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,502 @@
+use std::ops::{BitAnd, BitOr};
+use thiserror::Error;
+
+/// Version advertised in the hello exchange.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Longest client packet on the wire (card: header + 10 BCD bytes + message
+/// id + stamp).
+pub const MAX_CLIENT_PACKET: usize = 11 + MESSAGE_ID_LEN + STAMP_LEN;
+
+/// Longest server packet on the wire (LED frame: header + colours).
+pub const MAX_SERVER_PACKET: usize = 1 + LED_FRAME_LEN;
+
+/// Slider LEDs: 16 keys and the 15 dividers between them, left to right.
+pub const SLIDER_LEDS: usize = 31;
+/// Air tower LEDs: left tower bottom to top, then right tower bottom to top.
+pub const AIR_LEDS: usize = 6;
+/// LED payload size: one RGB triple per LED, slider first.
+pub const LED_FRAME_LEN: usize = 3 * (SLIDER_LEDS + AIR_LEDS);
+
+/// Trailer size of a stamped packet: u16 sequence + u32 microseconds, LE.
+pub const STAMP_LEN: usize = 6;
+/// Trailer size of a reliable message: u16 id, LE, before any stamp.
+pub const MESSAGE_ID_LEN: usize = 2;
+
+/// Header bit 7: set on every client packet sent over TCP.
+const TCP_BIT: u8 = 0x80;
+/// Header bit 6: set on frames originating from the server.
+const SERVER_BIT: u8 = 0x40;
+/// Header bits 5-4: packet type.
+const TYPE_MASK: u8 = 0x30;
+/// Header bit 3: the packet ends with a `Stamp` trailer. Servers that do not
+/// know the extension never see it unless the client enables stamping.
+const STAMP_BIT: u8 = 0x08;
+/// Header bit 2 selects an extended variant of the type: with air+slider the
+/// frame also carries the button mask (full state), with sync it is a ping,
+/// with card it is a hello. From the server, with ack, it is a hello ack.
+/// Servers that predate the handshake drop a card of the wrong length, so a
+/// hello never reaches them as a sync.
+const EXTENDED_BIT: u8 = 0x04;
+/// Header bit 1: the packet is a reliable message and ends with its id (then
+/// the stamp, if any). The server answers with `ServerPacket::Ack`.
+const MESSAGE_ID_BIT: u8 = 0x02;
+
+const TYPE_SYNC: u8 = 0b00;
+const TYPE_BUTTONS: u8 = 0b01;
+const TYPE_AIR_SLIDER: u8 = 0b10;
+const TYPE_CARD: u8 = 0b11;
+/// Server-only types: LED colours, ping echo, message ack.
+const TYPE_LED: u8 = 0b01;
+const TYPE_PONG: u8 = 0b10;
+const TYPE_ACK: u8 = 0b11;
+
+/// Sync payloads: "switch me on" / "switch me off".
+const SYNC_CONNECT: u8 = (1 << 5) | (1 << 4);
+const SYNC_DISCONNECT: u8 = 1 << 7;
+/// Hello / hello ack length: header, version, features (u16 LE), then the
+/// pairing nonce (u64 LE).
+const HELLO_LEN: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum ProtocolError {
+    #[error("empty frame")]
+    Empty,
+    #[error("bad frame length for {kind}: expected {expected} bytes, got {actual}")]
+    Length { kind: &'static str, expected: usize, actual: usize },
+    #[error("unknown header 0x{0:02x}")]
+    UnknownHeader(u8),
+    #[error("invalid sync payload 0x{0:02x}")]
+    InvalidSyncPayload(u8),
+}
+
+/// Optional protocol features, advertised in the hello exchange. Each side
+/// only uses what both advertised.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Features(pub u16);
+
+impl Features {
+    pub const NONE: Features = Features(0);
+    /// Server -> client LED frames.
+    pub const LED: Features = Features(1 << 0);
+    /// Ping / pong.
+    pub const PING: Features = Features(1 << 1);
+    /// `Stamp` trailers on client packets.
+    pub const STAMP: Features = Features(1 << 2);
+    /// `ClientPacket::FullState`.
+    pub const FULL_STATE: Features = Features(1 << 3);
+    /// Acked, retransmitted sync and card messages over UDP.
+    pub const RELIABLE: Features = Features(1 << 4);
+    /// Everything this build implements.
+    pub const ALL: Features = Features(0x001F);
+
+    pub fn contains(self, other: Features) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Features {
+    type Output = Features;
+
+    fn bitor(self, rhs: Features) -> Features {
+        Features(self.0 | rhs.0)
+    }
+}
+
+impl BitAnd for Features {
+    type Output = Features;
+
+    fn bitand(self, rhs: Features) -> Features {
+        Features(self.0 & rhs.0)
+    }
+}
+
+/// Client -> server packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientPacket {
+    /// Ask the server to switch to `connected`.
+    Sync { target_connected: bool },
+    /// Coin / service / test bits.
+    Buttons(u8),
+    AirSlider { air: u8, slider: u32 },
+    /// Buttons, air and slider sampled together.
+    FullState { buttons: u8, air: u8, slider: u32 },
+    /// Round-trip probe; the server echoes it back as `ServerPacket::Pong`.
+    Ping { id: u16, micros: u32 },
+    /// Opens the handshake; answered by `ServerPacket::HelloAck`. `nonce`
+    /// names the client's pairing session (see `auth`).
+    Hello { version: u8, features: Features, nonce: u64 },
+    /// Access code, 20 digits packed as BCD.
+    Card([u8; 10]),
+}
+
+/// Optional per-packet trailer for loss, reordering and latency analysis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stamp {
+    /// Wrapping counter, +1 per packet sent.
+    pub seq: u16,
+    /// Send time in microseconds on the client's monotonic clock, wrapping.
+    pub micros: u32,
+}
+
+/// Colours of every cabinet LED, as RGB triples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LedFrame {
+    pub slider: [[u8; 3]; SLIDER_LEDS],
+    pub air: [[u8; 3]; AIR_LEDS],
+}
+
+impl Default for LedFrame {
+    fn default() -> Self {
+        Self { slider: [[0; 3]; SLIDER_LEDS], air: [[0; 3]; AIR_LEDS] }
+    }
+}
+
+impl LedFrame {
+    /// The wire payload layout, also handed to Kotlin as is.
+    pub fn to_bytes(&self) -> [u8; LED_FRAME_LEN] {
+        let mut out = [0u8; LED_FRAME_LEN];
+        for (dst, rgb) in out.chunks_exact_mut(3).zip(self.slider.iter().chain(&self.air)) {
+            dst.copy_from_slice(rgb);
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8; LED_FRAME_LEN]) -> Self {
+        let mut frame = Self::default();
+        for (rgb, src) in frame.slider.iter_mut().chain(&mut frame.air).zip(bytes.chunks_exact(3)) {
+            rgb.copy_from_slice(src);
+        }
+        frame
+    }
+}
+
+/// Server -> client packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerPacket {
+    /// The server switched to `connected` in response to a sync request.
+    SyncConfirm { connected: bool },
+    Led(LedFrame),
+    /// Echo of a `ClientPacket::Ping`.
+    Pong { id: u16, micros: u32 },
+    /// The server's version and features, in answer to a hello, and the
+    /// nonce of its pairing session.
+    HelloAck { version: u8, features: Features, nonce: u64 },
+    /// Receipt for the reliable message with this id.
+    Ack { id: u16 },
+}
+
+/// An encoded packet in a fixed buffer, so the send loop never allocates.
+#[derive(Debug, Clone, Copy)]
+pub struct Encoded<const N: usize = MAX_CLIENT_PACKET> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> Encoded<N> {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+fn check_len(kind: &'static str, bytes: &[u8], expected: usize) -> Result<(), ProtocolError> {
+    if bytes.len() != expected {
+        return Err(ProtocolError::Length { kind, expected, actual: bytes.len() });
+    }
+    Ok(())
+}
+
+/// Ping/pong body: u16 id and u32 microseconds, LE, after the header.
+fn read_id_micros(bytes: &[u8]) -> (u16, u32) {
+    (
+        u16::from_le_bytes([bytes[1], bytes[2]]),
+        u32::from_le_bytes([bytes[3], bytes[4], bytes[5], bytes[6]]),
+    )
+}
+
+fn write_hello(buf: &mut [u8], version: u8, features: Features, nonce: u64) {
+    buf[1] = version;
+    buf[2..4].copy_from_slice(&features.0.to_le_bytes());
+    buf[4..12].copy_from_slice(&nonce.to_le_bytes());
+}
+
+fn read_hello(bytes: &[u8]) -> (u8, Features, u64) {
+    let features = Features(u16::from_le_bytes([bytes[2], bytes[3]]));
+    (bytes[1], features, u64::from_le_bytes(bytes[4..12].try_into().expect("8 bytes")))
+}
+
+/// Whether `header` opens a hello or a hello ack, in either direction.
+/// Pairing looks at this before the frame is decoded.
+pub fn is_handshake(header: u8) -> bool {
+    header & (TYPE_MASK | EXTENDED_BIT) == TYPE_MASK | EXTENDED_BIT
+}
+
+impl ClientPacket {
+    pub fn encode(&self, transport: Transport) -> Encoded {
+        self.encode_stamped(transport, None)
+    }
+
+    pub fn encode_stamped(&self, transport: Transport, stamp: Option<Stamp>) -> Encoded {
+        self.encode_message(transport, None, stamp)
+    }
+
+    /// Encodes with every optional trailer: the reliable message id, then
+    /// the stamp.
+    pub fn encode_message(&self, transport: Transport, id: Option<u16>, stamp: Option<Stamp>) -> Encoded {
+        let mut buf = [0u8; MAX_CLIENT_PACKET];
+        let protocol_bit = match transport {
+            Transport::Udp => 0,
+            Transport::Tcp => TCP_BIT,
+        };
+
+        let (type_bits, len) = match *self {
+            ClientPacket::Sync { target_connected } => {
+                buf[1] = if target_connected { SYNC_CONNECT } else { SYNC_DISCONNECT };
+                (TYPE_SYNC, 2)
+            }
+            ClientPacket::Buttons(mask) => {
+                buf[1] = mask;
+                (TYPE_BUTTONS, 2)
+            }
+            ClientPacket::AirSlider { air, slider } => {
+                buf[1] = air;
+                buf[2..6].copy_from_slice(&slider.to_le_bytes());
+                (TYPE_AIR_SLIDER, 6)
+            }
+            ClientPacket::FullState { buttons, air, slider } => {
+                buf[1] = buttons;
+                buf[2] = air;
+                buf[3..7].copy_from_slice(&slider.to_le_bytes());
+                (TYPE_AIR_SLIDER, 7)
+            }
+            ClientPacket::Ping { id, micros } => {
+                buf[1..3].copy_from_slice(&id.to_le_bytes());
+                buf[3..7].copy_from_slice(&micros.to_le_bytes());
+                (TYPE_SYNC, 7)
+            }
+            ClientPacket::Hello { version, features, nonce } => {
+                write_hello(&mut buf, version, features, nonce);
+                (TYPE_CARD, HELLO_LEN)
+            }
+            ClientPacket::Card(bcd) => {
+                buf[1..11].copy_from_slice(&bcd);
+                (TYPE_CARD, 11)
+            }
+        };
+        buf[0] = protocol_bit | (type_bits << 4);
+        if let ClientPacket::FullState { .. } | ClientPacket::Ping { .. } | ClientPacket::Hello { .. } = self {
+            buf[0] |= EXTENDED_BIT;
+        }
+
+        let mut len = len;
+        if let Some(id) = id {
+            buf[0] |= MESSAGE_ID_BIT;
+            buf[len..len + MESSAGE_ID_LEN].copy_from_slice(&id.to_le_bytes());
+            len += MESSAGE_ID_LEN;
+        }
+        if let Some(stamp) = stamp {
+            buf[0] |= STAMP_BIT;
+            buf[len..len + 2].copy_from_slice(&stamp.seq.to_le_bytes());
+            buf[len + 2..len + STAMP_LEN].copy_from_slice(&stamp.micros.to_le_bytes());
+            len += STAMP_LEN;
+        }
+
+        Encoded { buf, len }
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<(ClientPacket, Transport), ProtocolError> {
+        Self::decode_stamped(bytes).map(|(packet, transport, _)| (packet, transport))
+    }
+
+    pub fn decode_stamped(bytes: &[u8]) -> Result<(ClientPacket, Transport, Option<Stamp>), ProtocolError> {
+        Self::decode_message(bytes).map(|(packet, transport, _, stamp)| (packet, transport, stamp))
+    }
+
+    /// Decodes with every optional trailer, as `(packet, transport, message
+    /// id, stamp)`.
+    #[allow(clippy::type_complexity)]
+    pub fn decode_message(
+        bytes: &[u8],
+    ) -> Result<(ClientPacket, Transport, Option<u16>, Option<Stamp>), ProtocolError> {
+        let header = *bytes.first().ok_or(ProtocolError::Empty)?;
+        if header & !(TCP_BIT | TYPE_MASK | STAMP_BIT | EXTENDED_BIT | MESSAGE_ID_BIT) != 0 {
+            return Err(ProtocolError::UnknownHeader(header));
+        }
+        let extended = header & EXTENDED_BIT != 0;
+        if extended && (header & TYPE_MASK) >> 4 == TYPE_BUTTONS {
+            return Err(ProtocolError::UnknownHeader(header));
+        }
+        let transport = if header & TCP_BIT != 0 { Transport::Tcp } else { Transport::Udp };
+
+        let (bytes, stamp) = if header & STAMP_BIT != 0 {
+            let Some(split) = bytes.len().checked_sub(STAMP_LEN).filter(|&n| n >= 1) else {
+                return Err(ProtocolError::Length { kind: "stamp", expected: 1 + STAMP_LEN, actual: bytes.len() });
+            };
+            let (body, trailer) = bytes.split_at(split);
+            let stamp = Stamp {
+                seq: u16::from_le_bytes([trailer[0], trailer[1]]),
+                micros: u32::from_le_bytes([trailer[2], trailer[3], trailer[4], trailer[5]]),
+            };
+            (body, Some(stamp))
+        } else {
+            (bytes, None)
+        };
+
+        let (bytes, id) = if header & MESSAGE_ID_BIT != 0 {
+            let Some(split) = bytes.len().checked_sub(MESSAGE_ID_LEN).filter(|&n| n >= 1) else {
+                return Err(ProtocolError::Length {
+                    kind: "message id",
+                    expected: 1 + MESSAGE_ID_LEN,
+                    actual: bytes.len(),
+                });
+            };
+            let (body, trailer) = bytes.split_at(split);
+            (body, Some(u16::from_le_bytes([trailer[0], trailer[1]])))
+        } else {
+            (bytes, None)
+        };
+
+        let packet = match (header & TYPE_MASK) >> 4 {
+            TYPE_SYNC if extended => {
+                check_len("ping", bytes, 7)?;
+                let (id, micros) = read_id_micros(bytes);
+                ClientPacket::Ping { id, micros }
+            }
+            TYPE_SYNC => {
+                check_len("sync", bytes, 2)?;
+                match bytes[1] {
+                    SYNC_CONNECT => ClientPacket::Sync { target_connected: true },
+                    SYNC_DISCONNECT => ClientPacket::Sync { target_connected: false },
+                    other => return Err(ProtocolError::InvalidSyncPayload(other)),
+                }
+            }
+            TYPE_BUTTONS => {
+                check_len("buttons", bytes, 2)?;
+                ClientPacket::Buttons(bytes[1])
+            }
+            TYPE_AIR_SLIDER if extended => {
+                check_len("full state", bytes, 7)?;
+                let slider = u32::from_le_bytes([bytes[3], bytes[4], bytes[5], bytes[6]]);
+                ClientPacket::FullState { buttons: bytes[1], air: bytes[2], slider }
+            }
+            TYPE_AIR_SLIDER => {
+                check_len("air+slider", bytes, 6)?;
+                let slider = u32::from_le_bytes([bytes[2], bytes[3], bytes[4], bytes[5]]);
+                ClientPacket::AirSlider { air: bytes[1], slider }
+            }
+            _ if extended => {
+                check_len("hello", bytes, HELLO_LEN)?;
+                let (version, features, nonce) = read_hello(bytes);
+                ClientPacket::Hello { version, features, nonce }
+            }
+            _ => {
+                check_len("card", bytes, 11)?;
+                let mut bcd = [0u8; 10];
+                bcd.copy_from_slice(&bytes[1..11]);
+                ClientPacket::Card(bcd)
+            }
+        };
+        Ok((packet, transport, id, stamp))
+    }
+}
+
+impl ServerPacket {
+    pub fn encode(&self, transport: Transport) -> Encoded<MAX_SERVER_PACKET> {
+        let mut buf = [0u8; MAX_SERVER_PACKET];
+        let protocol_bit = match transport {
+            Transport::Udp => 0,
+            Transport::Tcp => TCP_BIT,
+        };
+        let (type_bits, len) = match self {
+            ServerPacket::SyncConfirm { connected } => {
+                buf[1] = (*connected as u8) << 4;
+                (TYPE_SYNC, 2)
+            }
+            ServerPacket::Led(frame) => {
+                buf[1..].copy_from_slice(&frame.to_bytes());
+                (TYPE_LED, 1 + LED_FRAME_LEN)
+            }
+            ServerPacket::Pong { id, micros } => {
+                buf[1..3].copy_from_slice(&id.to_le_bytes());
+                buf[3..7].copy_from_slice(&micros.to_le_bytes());
+                (TYPE_PONG, 7)
+            }
+            ServerPacket::HelloAck { version, features, nonce } => {
+                write_hello(&mut buf, *version, *features, *nonce);
+                (TYPE_ACK, HELLO_LEN)
+            }
+            ServerPacket::Ack { id } => {
+                buf[1..3].copy_from_slice(&id.to_le_bytes());
+                (TYPE_ACK, 3)
+            }
+        };
+        buf[0] = protocol_bit | SERVER_BIT | (type_bits << 4);
+        if let ServerPacket::HelloAck { .. } = self {
+            buf[0] |= EXTENDED_BIT;
+        }
+        Encoded { buf, len }
+    }
+
+    /// The low nibble of the header and the sync confirm payload bits other
+    /// than bit 4 are reserved and ignored, as the server leaves them
+    /// unspecified. Only ack, a type older servers never send, reads the
+    /// extended bit: it marks a hello ack.
+    pub fn decode(bytes: &[u8]) -> Result<ServerPacket, ProtocolError> {
+        let header = *bytes.first().ok_or(ProtocolError::Empty)?;
+        if header & SERVER_BIT == 0 {
+            return Err(ProtocolError::UnknownHeader(header));
+        }
+        match (header & TYPE_MASK) >> 4 {
+            TYPE_SYNC => {
+                check_len("sync confirm", bytes, 2)?;
+                Ok(ServerPacket::SyncConfirm { connected: (bytes[1] >> 4) & 1 == 1 })
+            }
+            TYPE_LED => {
+                check_len("led", bytes, 1 + LED_FRAME_LEN)?;
+                let mut payload = [0u8; LED_FRAME_LEN];
+                payload.copy_from_slice(&bytes[1..]);
+                Ok(ServerPacket::Led(LedFrame::from_bytes(&payload)))
+            }
+            TYPE_PONG => {
+                check_len("pong", bytes, 7)?;
+                let (id, micros) = read_id_micros(bytes);
+                Ok(ServerPacket::Pong { id, micros })
+            }
+            _ if header & EXTENDED_BIT != 0 => {
+                check_len("hello ack", bytes, HELLO_LEN)?;
+                let (version, features, nonce) = read_hello(bytes);
+                Ok(ServerPacket::HelloAck { version, features, nonce })
+            }
+            _ => {
+                check_len("ack", bytes, 3)?;
+                Ok(ServerPacket::Ack { id: u16::from_le_bytes([bytes[1], bytes[2]]) })
+            }
+        }
+    }
+}
+
+/// Appends `payload` to `out` with the 2-byte little-endian length prefix used
+/// on the TCP stream.
+pub fn write_tcp_frame(payload: &[u8], out: &mut Vec<u8>) {
+    out.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// Splits the first complete length-prefixed frame off `buf`. Returns the
+/// payload and the number of bytes consumed, or `None` if more data is needed.
+pub fn split_tcp_frame(buf: &[u8]) -> Option<(&[u8], usize)> {
+    if buf.len() < 2 {
+        return None;
+    }
+    let frame_len = u16::from_le_bytes([buf[0], buf[1]]) as usize;
+    if buf.len() < 2 + frame_len {
+        return None;
+    }
+    Some((&buf[2..2 + frame_len], 2 + frame_len))
+}
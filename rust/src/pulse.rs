@@ -1,43 +1,309 @@
-use std::time::Instant;
+//! The air pulse of Flick and Auto modes: the swept zones light one after
+//! another, like a hand passing through the IR beams, while the baseline
+//! zones stay lit throughout.
+//!
+//! A flick sweeps as the profile says; an air-down trigger sweeps the same
+//! zones from the top down, for air-action notes that need the hand to come
+//! down through the sensor. Sweeps never cut off one of the other kind: an
+//! air-down during a flick's sweep (or a flick during an air-down) waits
+//! until the running one ends, so both are seen whole. A trigger of the
+//! running kind follows the `RetriggerPolicy`.
+//!
+//! Pure, with the caller passing the clock, so profiles can be checked step
+//! by step in tests.
 
+use std::collections::VecDeque;
+use crate::air::AIR_ZONES;
+use crate::config::ConfigError;
+
+/// Every air zone, as bits.
+pub const AIR_MASK: u8 = (1 << AIR_ZONES) - 1;
+
+/// The direction a sweep runs through the zones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ZoneOrder {
+    /// Lowest zone first.
+    #[default]
+    Up = 0,
+    Down = 1,
+    /// Up and straight back down, without repeating the top zone.
+    Both = 2,
+}
+
+impl TryFrom<u32> for ZoneOrder {
+    type Error = ConfigError;
+
+    fn try_from(raw: u32) -> Result<Self, ConfigError> {
+        match raw {
+            0 => Ok(ZoneOrder::Up),
+            1 => Ok(ZoneOrder::Down),
+            2 => Ok(ZoneOrder::Both),
+            other => Err(ConfigError::InvalidPulseProfile(format!("unknown zone order {other}"))),
+        }
+    }
+}
+
+/// What one trigger produces. The default is five 10 ms steps from zone 1
+/// up to zone 5, with zone 6 held.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PulseProfile {
+    /// How long each swept zone stays lit.
+    pub step_ns: u64,
+    pub order: ZoneOrder,
+    /// Zones lit whenever the pulse drives the air byte; every other zone
+    /// is swept.
+    pub baseline: u8,
+    /// Sweeps per trigger, back to back.
+    pub sweeps: u32,
+}
+
+impl Default for PulseProfile {
+    fn default() -> Self {
+        Self { step_ns: 10_000_000, order: ZoneOrder::Up, baseline: 0b10_0000, sweeps: 1 }
+    }
+}
+
+impl PulseProfile {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |why: &str| Err(ConfigError::InvalidPulseProfile(why.into()));
+        if self.step_ns == 0 {
+            return invalid("step duration must be positive");
+        }
+        if self.sweeps == 0 {
+            return invalid("at least one sweep is needed");
+        }
+        if self.baseline & !AIR_MASK != 0 {
+            return invalid("baseline names a zone past zone 6");
+        }
+        if self.baseline == AIR_MASK {
+            return invalid("no zone is left to sweep");
+        }
+        Ok(())
+    }
+
+    /// The zone bits of one sweep of `kind`, in order, and how many there
+    /// are.
+    fn sequence(&self, kind: Sweep) -> ([u8; 2 * AIR_ZONES], usize) {
+        let mut sequence = [0; 2 * AIR_ZONES];
+        let mut len = 0;
+        let up = (0..AIR_ZONES).map(|i| 1u8 << i).filter(|bit| self.baseline & bit == 0);
+        let mut push = |bit| {
+            sequence[len] = bit;
+            len += 1;
+        };
+        let order = match kind {
+            Sweep::Flick => self.order,
+            Sweep::AirDown => ZoneOrder::Down,
+        };
+        match order {
+            ZoneOrder::Up => up.for_each(&mut push),
+            ZoneOrder::Down => up.rev().for_each(&mut push),
+            ZoneOrder::Both => {
+                up.clone().for_each(&mut push);
+                up.rev().skip(1).for_each(&mut push);
+            }
+        }
+        (sequence, len)
+    }
+}
+
+/// What a trigger does while a sweep of its own kind is running.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RetriggerPolicy {
+    /// Starts the sweep over, cutting the running one short.
+    #[default]
+    Restart = 0,
+    /// Runs another whole sweep after the running one (and any already
+    /// waiting), so fast consecutive air notes each get their own.
+    Queue = 1,
+    /// Drops the trigger.
+    Ignore = 2,
+}
+
+impl TryFrom<u32> for RetriggerPolicy {
+    type Error = ConfigError;
+
+    fn try_from(raw: u32) -> Result<Self, ConfigError> {
+        match raw {
+            0 => Ok(RetriggerPolicy::Restart),
+            1 => Ok(RetriggerPolicy::Queue),
+            2 => Ok(RetriggerPolicy::Ignore),
+            other => Err(ConfigError::InvalidPulseProfile(format!("unknown retrigger policy {other}"))),
+        }
+    }
+}
+
+/// Sweeps waiting behind the running one; triggers beyond are dropped.
+pub const MAX_WAITING: usize = 8;
+
+/// What started a sweep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sweep {
+    /// Runs the profile's zone order.
+    Flick,
+    /// Always runs from the top zone down.
+    AirDown,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sequence {
+    zones: [u8; 2 * AIR_ZONES],
+    len: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Running {
+    kind: Sweep,
+    start_ns: u64,
+}
+
+#[derive(Debug, Clone)]
 pub struct PulseState {
-    pub start_time: Option<Instant>,
+    profile: PulseProfile,
+    flick: Sequence,
+    air_down: Sequence,
+    retrigger: RetriggerPolicy,
+    running: Option<Running>,
+    /// Sweeps to run once the running one ends, oldest first.
+    waiting: VecDeque<Sweep>,
+}
+
+impl Default for PulseState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PulseState {
     pub fn new() -> Self {
-        Self { start_time: None }
+        Self::with_profile(PulseProfile::default())
     }
 
-    pub fn trigger(&mut self) {
-        self.start_time = Some(Instant::now());
+    /// `profile` must have passed `PulseProfile::validate`.
+    pub fn with_profile(profile: PulseProfile) -> Self {
+        let mut state = Self {
+            profile,
+            flick: Sequence { zones: [0; 2 * AIR_ZONES], len: 0 },
+            air_down: Sequence { zones: [0; 2 * AIR_ZONES], len: 0 },
+            retrigger: RetriggerPolicy::default(),
+            running: None,
+            waiting: VecDeque::with_capacity(MAX_WAITING),
+        };
+        state.set_profile(profile);
+        state
     }
 
-    pub fn get_air_byte(&mut self, is_auto: bool) -> u8 {
-        let mut byte: u8 = 0b00100000;
+    pub fn profile(&self) -> PulseProfile {
+        self.profile
+    }
 
-        if let Some(start) = self.start_time {
-            let elapsed = start.elapsed().as_millis();
+    /// Replaces the profile (see `with_profile`), ending the pulse.
+    pub fn set_profile(&mut self, profile: PulseProfile) {
+        let sequence = |kind| {
+            let (zones, len) = profile.sequence(kind);
+            Sequence { zones, len }
+        };
+        self.profile = profile;
+        self.flick = sequence(Sweep::Flick);
+        self.air_down = sequence(Sweep::AirDown);
+        self.stop();
+    }
 
-            if elapsed < 10 {
-                byte |= 1 << 0;
-            } else if elapsed < 20 {
-                byte |= 1 << 1;
-            } else if elapsed < 30 {
-                byte |= 1 << 2;
-            } else if elapsed < 40 {
-                byte |= 1 << 3;
-            } else if elapsed < 50 {
-                byte |= 1 << 4;
-            } else {
-                if is_auto {
-                    self.start_time = Some(Instant::now());
-                } else {
-                    self.start_time = None;
+    pub fn retrigger(&self) -> RetriggerPolicy {
+        self.retrigger
+    }
+
+    pub fn set_retrigger(&mut self, policy: RetriggerPolicy) {
+        self.retrigger = policy;
+    }
+
+    /// Starts a flick's sweep at `now_ns`, or handles it per the policies
+    /// in the module docs.
+    pub fn trigger(&mut self, now_ns: u64) {
+        self.trigger_sweep(Sweep::Flick, now_ns);
+    }
+
+    /// Starts an air-down sweep, or handles it like `trigger`.
+    pub fn trigger_air_down(&mut self, now_ns: u64) {
+        self.trigger_sweep(Sweep::AirDown, now_ns);
+    }
+
+    fn trigger_sweep(&mut self, kind: Sweep, now_ns: u64) {
+        let Some(running) = self.running else {
+            self.running = Some(Running { kind, start_ns: now_ns });
+            return;
+        };
+        let queue = if running.kind == kind {
+            match self.retrigger {
+                RetriggerPolicy::Restart => {
+                    self.running = Some(Running { kind, start_ns: now_ns });
+                    return;
                 }
+                RetriggerPolicy::Queue => true,
+                RetriggerPolicy::Ignore => false,
+            }
+        } else {
+            // The other kind always gets its turn, once unless queueing.
+            self.retrigger == RetriggerPolicy::Queue || !self.waiting.contains(&kind)
+        };
+        if queue && self.waiting.len() < MAX_WAITING {
+            self.waiting.push_back(kind);
+        }
+    }
+
+    /// Sweeps waiting behind the running one.
+    pub fn waiting(&self) -> usize {
+        self.waiting.len()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.running.is_some()
+    }
+
+    /// The sweep running now, if any.
+    pub fn running(&self) -> Option<Sweep> {
+        self.running.map(|r| r.kind)
+    }
+
+    /// Ends the pulse and drops the waiting sweeps, keeping the profile and
+    /// the policy.
+    pub fn stop(&mut self) {
+        self.running = None;
+        self.waiting.clear();
+    }
+
+    fn sequence(&self, kind: Sweep) -> &Sequence {
+        match kind {
+            Sweep::Flick => &self.flick,
+            Sweep::AirDown => &self.air_down,
+        }
+    }
+
+    /// The air byte at `now_ns`. A finished sweep hands over to the next
+    /// waiting one, which starts right where it ended; otherwise the pulse ends, or
+    /// in Auto mode (`is_auto`) starts over.
+    pub fn get_air_byte(&mut self, now_ns: u64, is_auto: bool) -> u8 {
+        let mut byte = self.profile.baseline;
+
+        while let Some(running) = self.running {
+            let sequence = self.sequence(running.kind);
+            let steps = sequence.len as u64 * u64::from(self.profile.sweeps);
+            let step = now_ns.saturating_sub(running.start_ns) / self.profile.step_ns;
+            if step < steps {
+                byte |= sequence.zones[(step % sequence.len as u64) as usize];
+                break;
+            }
+            if let Some(kind) = self.waiting.pop_front() {
+                let end_ns = running.start_ns + steps * self.profile.step_ns;
+                self.running = Some(Running { kind, start_ns: end_ns });
+            } else if is_auto {
+                self.running = Some(Running { kind: Sweep::Flick, start_ns: now_ns });
+                break;
+            } else {
+                self.running = None;
             }
         }
 
         byte
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,172 @@
+//! Acked delivery for one-shot control messages over UDP.
+//!
+//! Sync requests and card swipes are intents, not samples: losing one loses
+//! the user's action. Once `Features::RELIABLE` is agreed they go out once
+//! with a message id and are retransmitted with exponential backoff until
+//! the server acks that id; input packets stay fire-and-forget. A held card
+//! still goes out on every tick in between, without an id, as servers that
+//! poll the card expect. A sync stays pending past its ack until the
+//! server's confirm arrives, since the confirm is a plain datagram: a lost
+//! one is answered again by the next retransmission. Like `DeltaGate`, the
+//! sender is pure and runs against a simulated clock in tests.
+
+use crate::protocol::ClientPacket;
+
+/// First retransmission timeout, doubled after every attempt.
+pub const INITIAL_RTO_NS: u64 = 20_000_000;
+pub const MAX_RTO_NS: u64 = 320_000_000;
+/// Sends of one message, first one included, before giving up. With the
+/// defaults the last retry goes out just before the sync timeout.
+pub const MAX_ATTEMPTS: u32 = 5;
+/// Message ids a receiver remembers.
+pub const DEDUPE_WINDOW: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Pending {
+    id: u16,
+    packet: ClientPacket,
+    attempts: u32,
+    rto_ns: u64,
+    next_ns: u64,
+}
+
+/// Client side. Owned behind `Shared`, since acks arrive on the receive path.
+#[derive(Debug, Clone)]
+pub struct ReliableSender {
+    next_id: u16,
+    pending: Option<Pending>,
+    /// The message acked (or given up on) last; repeats of it due on later
+    /// ticks are the same intent and stay quiet.
+    done: Option<ClientPacket>,
+    retransmits: u64,
+    given_up: u64,
+}
+
+impl ReliableSender {
+    /// `first_id` should differ between sessions, so a server still holding
+    /// ids from the previous one does not drop the new ones as duplicates.
+    pub fn new(first_id: u16) -> Self {
+        Self { next_id: first_id, pending: None, done: None, retransmits: 0, given_up: 0 }
+    }
+
+    /// Whether `packet` is a control message rather than an input sample.
+    pub fn is_control(packet: &ClientPacket) -> bool {
+        matches!(packet, ClientPacket::Sync { .. } | ClientPacket::Card(_))
+    }
+
+    /// For a control `packet` due on a tick at `now_ns`: the message id to
+    /// send it with (first send or retransmission), or `None` to skip it.
+    pub fn poll(&mut self, packet: &ClientPacket, now_ns: u64) -> Option<u16> {
+        if self.done == Some(*packet) {
+            return None;
+        }
+        match &mut self.pending {
+            Some(p) if p.packet == *packet => {
+                if now_ns < p.next_ns {
+                    return None;
+                }
+                if p.attempts >= MAX_ATTEMPTS {
+                    self.given_up += 1;
+                    self.done = Some(p.packet);
+                    self.pending = None;
+                    return None;
+                }
+                p.attempts += 1;
+                p.rto_ns = (p.rto_ns * 2).min(MAX_RTO_NS);
+                p.next_ns = now_ns + p.rto_ns;
+                self.retransmits += 1;
+                Some(p.id)
+            }
+            _ => {
+                let id = self.next_id;
+                self.next_id = id.wrapping_add(1);
+                self.done = None;
+                self.pending = Some(Pending {
+                    id,
+                    packet: *packet,
+                    attempts: 1,
+                    rto_ns: INITIAL_RTO_NS,
+                    next_ns: now_ns + INITIAL_RTO_NS,
+                });
+                Some(id)
+            }
+        }
+    }
+
+    /// An input packet (or nothing) was due instead, so the next control
+    /// message is a new intent even if it repeats the last one.
+    pub fn idle(&mut self) {
+        self.done = None;
+    }
+
+    /// Returns whether `id` acked the pending message. Stale acks are ignored.
+    /// An acked sync stays pending until `on_sync_confirm`.
+    pub fn on_ack(&mut self, id: u16) -> bool {
+        match self.pending {
+            Some(p) if p.id == id => {
+                if !matches!(p.packet, ClientPacket::Sync { .. }) {
+                    self.done = Some(p.packet);
+                    self.pending = None;
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The server confirmed it is `connected`, settling a pending sync
+    /// request for that state. Returns whether one was pending.
+    pub fn on_sync_confirm(&mut self, connected: bool) -> bool {
+        let confirmed = ClientPacket::Sync { target_connected: connected };
+        match self.pending {
+            Some(p) if p.packet == confirmed => {
+                self.done = Some(p.packet);
+                self.pending = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn pending(&self) -> Option<ClientPacket> {
+        self.pending.map(|p| p.packet)
+    }
+
+    pub fn retransmits(&self) -> u64 {
+        self.retransmits
+    }
+
+    pub fn given_up(&self) -> u64 {
+        self.given_up
+    }
+
+    /// Drops the pending message, for a restarted engine or a new server.
+    pub fn reset(&mut self, first_id: u16) {
+        *self = Self { retransmits: self.retransmits, given_up: self.given_up, ..Self::new(first_id) };
+    }
+}
+
+/// Server side: the last `DEDUPE_WINDOW` message ids, so a retransmission
+/// whose original arrived is acked again but not applied twice.
+#[derive(Debug, Clone, Default)]
+pub struct DedupeWindow {
+    recent: [Option<u16>; DEDUPE_WINDOW],
+    next: usize,
+}
+
+impl DedupeWindow {
+    /// Records `id`; false if it was seen within the window.
+    pub fn accept(&mut self, id: u16) -> bool {
+        if self.recent.contains(&Some(id)) {
+            return false;
+        }
+        self.recent[self.next] = Some(id);
+        self.next = (self.next + 1) % DEDUPE_WINDOW;
+        true
+    }
+
+    /// Forgets everything, e.g. when a client says hello again.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
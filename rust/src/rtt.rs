@@ -0,0 +1,140 @@
+//! Round-trip time from ping/pong frames.
+//!
+//! Smoothing follows RFC 6298 (gain 1/8 for the mean, 1/4 for the variance).
+//! Loss is a moving average with gain 1/8 over resolved pings: a ping counts
+//! as lost when a later one is answered first or after `PING_TIMEOUT`.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// A ping without pong after this long is lost.
+pub const PING_TIMEOUT_NS: u64 = 1_000_000_000;
+/// Pings kept waiting for a pong; the oldest is dropped as lost beyond this.
+const MAX_OUTSTANDING: usize = 16;
+const PPM: i64 = 1_000_000;
+
+/// A point-in-time copy of the estimates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RttSnapshot {
+    pub pings_sent: u64,
+    pub pongs_received: u64,
+    pub pings_lost: u64,
+    pub last_rtt_ns: u64,
+    pub min_rtt_ns: u64,
+    pub smoothed_rtt_ns: u64,
+    pub rtt_var_ns: u64,
+    /// Smoothed loss rate in parts per million.
+    pub loss_ppm: u64,
+}
+
+impl RttSnapshot {
+    /// Field order of the `long[]` handed to Kotlin.
+    pub fn to_array(&self) -> [i64; 8] {
+        [
+            self.pings_sent as i64,
+            self.pongs_received as i64,
+            self.pings_lost as i64,
+            self.last_rtt_ns as i64,
+            self.min_rtt_ns as i64,
+            self.smoothed_rtt_ns as i64,
+            self.rtt_var_ns as i64,
+            self.loss_ppm as i64,
+        ]
+    }
+}
+
+#[derive(Default)]
+struct RttState {
+    next_id: u16,
+    /// (id, send time) of unanswered pings, oldest first.
+    outstanding: VecDeque<(u16, u64)>,
+    stats: RttSnapshot,
+}
+
+impl RttState {
+    fn resolve(&mut self, lost: bool) {
+        let target = if lost { PPM } else { 0 };
+        let loss = self.stats.loss_ppm as i64;
+        self.stats.loss_ppm = (loss + (target - loss) / 8) as u64;
+        if lost {
+            self.stats.pings_lost += 1;
+        }
+    }
+
+    fn sample(&mut self, rtt: u64) {
+        let s = &mut self.stats;
+        if s.pongs_received == 0 {
+            s.smoothed_rtt_ns = rtt;
+            s.rtt_var_ns = rtt / 2;
+            s.min_rtt_ns = rtt;
+        } else {
+            let srtt = s.smoothed_rtt_ns as i64;
+            let var = s.rtt_var_ns as i64;
+            let err = rtt as i64 - srtt;
+            s.rtt_var_ns = (var + (err.abs() - var) / 4) as u64;
+            s.smoothed_rtt_ns = (srtt + err / 8) as u64;
+            s.min_rtt_ns = s.min_rtt_ns.min(rtt);
+        }
+        s.last_rtt_ns = rtt;
+        s.pongs_received += 1;
+    }
+}
+
+pub(crate) struct RttTracker {
+    state: Mutex<RttState>,
+}
+
+impl RttTracker {
+    pub fn new() -> Self {
+        Self { state: Mutex::new(RttState::default()) }
+    }
+
+    /// Registers a ping sent at `now_ns` and returns its id.
+    pub fn on_ping(&self, now_ns: u64) -> u16 {
+        let Ok(mut st) = self.state.lock() else { return 0 };
+        while let Some(&(_, sent)) = st.outstanding.front() {
+            if now_ns.saturating_sub(sent) < PING_TIMEOUT_NS && st.outstanding.len() < MAX_OUTSTANDING {
+                break;
+            }
+            st.outstanding.pop_front();
+            st.resolve(true);
+        }
+        let id = st.next_id;
+        st.next_id = id.wrapping_add(1);
+        st.outstanding.push_back((id, now_ns));
+        st.stats.pings_sent += 1;
+        id
+    }
+
+    /// Matches a pong against the outstanding pings. Unknown ids (late,
+    /// duplicated or from before a reset) are ignored.
+    pub fn on_pong(&self, id: u16, now_ns: u64) {
+        let Ok(mut st) = self.state.lock() else { return };
+        let Some(pos) = st.outstanding.iter().position(|&(i, _)| i == id) else {
+            return;
+        };
+        for _ in 0..pos {
+            st.outstanding.pop_front();
+            st.resolve(true);
+        }
+        if let Some((_, sent)) = st.outstanding.pop_front() {
+            st.resolve(false);
+            st.sample(now_ns.saturating_sub(sent));
+        }
+    }
+
+    /// Whether a pong is still expected, so the caller keeps receiving.
+    pub fn awaiting_pong(&self) -> bool {
+        self.state.lock().map(|st| !st.outstanding.is_empty()).unwrap_or(false)
+    }
+
+    pub fn snapshot(&self) -> RttSnapshot {
+        self.state.lock().map(|st| st.stats).unwrap_or_default()
+    }
+
+    pub fn reset(&self) {
+        if let Ok(mut st) = self.state.lock() {
+            *st = RttState::default();
+        }
+    }
+}
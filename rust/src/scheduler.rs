@@ -0,0 +1,108 @@
+//! Absolute-deadline tick scheduling for the send loop.
+//!
+//! Deadlines sit on a fixed grid `origin + n * interval`, so a late wakeup
+//! only delays that one send; it never shifts the slots after it. Time is
+//! passed in as nanoseconds since an arbitrary origin, which keeps the
+//! scheduler independent of the clock and testable with a simulated one.
+
+/// What to do with slots that passed while the loop was late.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissPolicy {
+    /// Fire missed slots back-to-back until caught up. At most `max_burst`
+    /// are kept; anything older is dropped and counted as missed.
+    CatchUp { max_burst: u32 },
+    /// Fire once, drop the other missed slots and resume on the grid.
+    Skip,
+}
+
+impl Default for MissPolicy {
+    fn default() -> Self {
+        MissPolicy::CatchUp { max_burst: 4 }
+    }
+}
+
+impl MissPolicy {
+    /// Packs the policy into a `u32` for lock-free sharing: 0 = skip,
+    /// n = catch up with a burst of n.
+    pub(crate) fn to_raw(self) -> u32 {
+        match self {
+            MissPolicy::Skip => 0,
+            MissPolicy::CatchUp { max_burst } => max_burst.max(1),
+        }
+    }
+
+    pub(crate) fn from_raw(raw: u32) -> Self {
+        match raw {
+            0 => MissPolicy::Skip,
+            n => MissPolicy::CatchUp { max_burst: n },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TickScheduler {
+    interval_ns: u64,
+    next_ns: u64,
+    policy: MissPolicy,
+}
+
+impl TickScheduler {
+    /// The first tick is due immediately at `now_ns`.
+    pub fn new(interval_ns: u64, policy: MissPolicy, now_ns: u64) -> Self {
+        Self {
+            interval_ns: interval_ns.max(1),
+            next_ns: now_ns,
+            policy,
+        }
+    }
+
+    pub fn interval_ns(&self) -> u64 {
+        self.interval_ns
+    }
+
+    pub fn policy(&self) -> MissPolicy {
+        self.policy
+    }
+
+    /// The deadline of the next slot that has not fired yet.
+    pub fn next_deadline(&self) -> u64 {
+        self.next_ns
+    }
+
+    pub fn set_policy(&mut self, policy: MissPolicy) {
+        self.policy = policy;
+    }
+
+    /// Changes the interval and re-anchors the grid at `now_ns`. A no-op if
+    /// the interval is unchanged, so it is cheap to call every iteration.
+    pub fn set_interval(&mut self, interval_ns: u64, now_ns: u64) {
+        let interval_ns = interval_ns.max(1);
+        if interval_ns != self.interval_ns {
+            self.interval_ns = interval_ns;
+            self.next_ns = now_ns;
+        }
+    }
+
+    /// Re-anchors the grid at `now_ns`, forgetting any backlog. Used when the
+    /// loop was intentionally not sending (no target, reconnecting).
+    pub fn reset(&mut self, now_ns: u64) {
+        self.next_ns = now_ns;
+    }
+
+    /// Returns `Some(missed)` when a tick should fire now, where `missed` is
+    /// the number of slots dropped by the policy on the way; `None` if the
+    /// next slot is still in the future.
+    pub fn poll(&mut self, now_ns: u64) -> Option<u64> {
+        if now_ns < self.next_ns {
+            return None;
+        }
+        let due = (now_ns - self.next_ns) / self.interval_ns + 1;
+
+        let missed = match self.policy {
+            MissPolicy::Skip => due - 1,
+            MissPolicy::CatchUp { max_burst } => due.saturating_sub(max_burst.max(1) as u64),
+        };
+        self.next_ns += (missed + 1) * self.interval_ns;
+        Some(missed)
+    }
+}
@@ -0,0 +1,131 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use thiserror::Error;
+
+/// Connection state of one engine.
+///
+/// `Syncing` remembers where the sync is heading, so a server confirm and a
+/// timeout both know exactly which state to land in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnState {
+    Idle,
+    Connected,
+    Syncing { target_connected: bool },
+}
+
+/// Everything that may move the state machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnEvent {
+    /// The connect button: Idle <-> Connected, or cancel a running sync.
+    Toggle,
+    /// Ask the server to switch to the opposite state.
+    SyncRequest,
+    /// The server acknowledged a sync and reports the state it switched to.
+    ServerConfirm { connected: bool },
+    /// No confirm arrived before the sync deadline.
+    Timeout,
+    /// The engine is going away; always lands in Idle.
+    Disconnect,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("illegal transition: {event:?} in state {from:?}")]
+pub struct IllegalTransition {
+    pub from: ConnState,
+    pub event: ConnEvent,
+}
+
+impl ConnState {
+    /// The value reported to Kotlin: 0 = suspended, 1 = active, 2 = waiting.
+    pub fn code(self) -> u32 {
+        match self {
+            ConnState::Idle => 0,
+            ConnState::Connected => 1,
+            ConnState::Syncing { .. } => 2,
+        }
+    }
+
+    /// Validates `event` against the transition table and returns the next
+    /// state. The state itself is never mutated here.
+    pub fn next(self, event: ConnEvent) -> Result<ConnState, IllegalTransition> {
+        use ConnEvent::*;
+        use ConnState::*;
+
+        let next = match (self, event) {
+            (Idle, Toggle) => Connected,
+            (Connected, Toggle) => Idle,
+            // WAITING: the connect button means "disconnect". Cancel the sync
+            // instead of ignoring the tap, otherwise leaving the page mid-sync
+            // would never stop the engine.
+            (Syncing { .. }, Toggle) => Idle,
+
+            (Idle, SyncRequest) => Syncing { target_connected: true },
+            (Connected, SyncRequest) => Syncing { target_connected: false },
+
+            (Syncing { target_connected }, ServerConfirm { connected })
+                if connected == target_connected =>
+            {
+                if connected { Connected } else { Idle }
+            }
+
+            // The sync never landed: fall back to where we came from.
+            (Syncing { target_connected }, Timeout) => {
+                if target_connected { Idle } else { Connected }
+            }
+
+            (_, Disconnect) => Idle,
+
+            _ => return Err(IllegalTransition { from: self, event }),
+        };
+        Ok(next)
+    }
+
+    fn to_raw(self) -> u32 {
+        match self {
+            ConnState::Idle => 0,
+            ConnState::Connected => 1,
+            ConnState::Syncing { target_connected: false } => 2,
+            ConnState::Syncing { target_connected: true } => 3,
+        }
+    }
+
+    fn from_raw(raw: u32) -> Self {
+        match raw {
+            1 => ConnState::Connected,
+            2 => ConnState::Syncing { target_connected: false },
+            3 => ConnState::Syncing { target_connected: true },
+            _ => ConnState::Idle,
+        }
+    }
+}
+
+/// Lock-free holder for a `ConnState`, shared between the UI, the engine loop
+/// and the TCP RX thread.
+pub(crate) struct StateCell(AtomicU32);
+
+impl StateCell {
+    pub fn new() -> Self {
+        Self(AtomicU32::new(ConnState::Idle.to_raw()))
+    }
+
+    pub fn load(&self) -> ConnState {
+        ConnState::from_raw(self.0.load(Ordering::Acquire))
+    }
+
+    /// Applies `event` atomically; concurrent events are serialized by CAS so
+    /// each one is validated against the state it actually moves.
+    pub fn apply(&self, event: ConnEvent) -> Result<ConnState, IllegalTransition> {
+        let mut current = self.0.load(Ordering::Acquire);
+        loop {
+            let next = ConnState::from_raw(current).next(event)?;
+            match self.0.compare_exchange_weak(
+                current,
+                next.to_raw(),
+                Ordering::SeqCst,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return Ok(next),
+                Err(actual) => current = actual,
+            }
+        }
+    }
+}
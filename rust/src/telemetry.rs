@@ -0,0 +1,178 @@
+//! Send-loop timing counters.
+//!
+//! The engine thread is the only writer; readers take a `snapshot` at any
+//! time without locking. Jitter (distance of each send interval from the
+//! configured one) goes into a log-linear histogram for the p99.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Values below this many microseconds get one bucket each.
+const LINEAR_US: u64 = 32;
+/// Sub-buckets per power of two above `LINEAR_US`.
+const SUB_BUCKETS: u64 = 16;
+const BUCKETS: usize = 32 + 20 * 16;
+
+fn bucket_of(us: u64) -> usize {
+    if us < LINEAR_US {
+        return us as usize;
+    }
+    let exp = 63 - us.leading_zeros() as u64;
+    let mantissa = (us >> (exp - 4)) & (SUB_BUCKETS - 1);
+    let idx = LINEAR_US + (exp - 5) * SUB_BUCKETS + mantissa;
+    (idx as usize).min(BUCKETS - 1)
+}
+
+/// Upper bound, in microseconds, of the values that land in `idx`.
+fn bucket_ceiling_us(idx: usize) -> u64 {
+    let idx = idx as u64;
+    if idx < LINEAR_US {
+        return idx + 1;
+    }
+    let exp = (idx - LINEAR_US) / SUB_BUCKETS + 5;
+    let mantissa = (idx - LINEAR_US) % SUB_BUCKETS;
+    (SUB_BUCKETS + mantissa + 1) << (exp - 4)
+}
+
+/// A point-in-time copy of the counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TelemetrySnapshot {
+    pub packets_sent: u64,
+    pub send_errors: u64,
+    pub missed_deadlines: u64,
+    /// Number of measured send intervals.
+    pub samples: u64,
+    pub min_interval_ns: u64,
+    pub max_interval_ns: u64,
+    pub mean_interval_ns: u64,
+    /// 99th percentile of |interval - target|, rounded up to its bucket.
+    pub p99_jitter_ns: u64,
+    pub target_interval_ns: u64,
+}
+
+impl TelemetrySnapshot {
+    /// Field order of the `long[]` handed to Kotlin.
+    pub fn to_array(&self) -> [i64; 9] {
+        [
+            self.packets_sent as i64,
+            self.send_errors as i64,
+            self.missed_deadlines as i64,
+            self.samples as i64,
+            self.min_interval_ns as i64,
+            self.max_interval_ns as i64,
+            self.mean_interval_ns as i64,
+            self.p99_jitter_ns as i64,
+            self.target_interval_ns as i64,
+        ]
+    }
+}
+
+pub(crate) struct Telemetry {
+    packets_sent: AtomicU64,
+    send_errors: AtomicU64,
+    missed_deadlines: AtomicU64,
+    samples: AtomicU64,
+    sum_interval_ns: AtomicU64,
+    min_interval_ns: AtomicU64,
+    max_interval_ns: AtomicU64,
+    /// Time of the previous send; 0 = no previous send to measure against.
+    last_send_ns: AtomicU64,
+    jitter_us: [AtomicU64; BUCKETS],
+}
+
+impl Telemetry {
+    pub fn new() -> Self {
+        Self {
+            packets_sent: AtomicU64::new(0),
+            send_errors: AtomicU64::new(0),
+            missed_deadlines: AtomicU64::new(0),
+            samples: AtomicU64::new(0),
+            sum_interval_ns: AtomicU64::new(0),
+            min_interval_ns: AtomicU64::new(u64::MAX),
+            max_interval_ns: AtomicU64::new(0),
+            last_send_ns: AtomicU64::new(0),
+            jitter_us: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+
+    /// Records a successful send at `now_ns` against the `target_ns` interval.
+    pub fn record_send(&self, now_ns: u64, target_ns: u64) {
+        self.packets_sent.fetch_add(1, Ordering::Relaxed);
+        let last = self.last_send_ns.swap(now_ns, Ordering::Relaxed);
+        if last == 0 || now_ns < last {
+            return;
+        }
+        let interval = now_ns - last;
+        self.samples.fetch_add(1, Ordering::Relaxed);
+        self.sum_interval_ns.fetch_add(interval, Ordering::Relaxed);
+        self.min_interval_ns.fetch_min(interval, Ordering::Relaxed);
+        self.max_interval_ns.fetch_max(interval, Ordering::Relaxed);
+        let jitter_us = interval.abs_diff(target_ns) / 1_000;
+        self.jitter_us[bucket_of(jitter_us)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_error(&self) {
+        self.send_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_missed(&self, slots: u64) {
+        if slots > 0 {
+            self.missed_deadlines.fetch_add(slots, Ordering::Relaxed);
+        }
+    }
+
+    /// The loop stopped sending on purpose (idle, no target); the next send
+    /// starts a fresh interval instead of counting the pause as jitter.
+    pub fn break_interval(&self) {
+        self.last_send_ns.store(0, Ordering::Relaxed);
+    }
+
+    pub fn reset(&self) {
+        for counter in [
+            &self.packets_sent,
+            &self.send_errors,
+            &self.missed_deadlines,
+            &self.samples,
+            &self.sum_interval_ns,
+            &self.max_interval_ns,
+            &self.last_send_ns,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        self.min_interval_ns.store(u64::MAX, Ordering::Relaxed);
+        for bucket in &self.jitter_us {
+            bucket.store(0, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self, target_interval_ns: u64) -> TelemetrySnapshot {
+        let samples = self.samples.load(Ordering::Relaxed);
+        let counts: Vec<u64> = self.jitter_us.iter().map(|b| b.load(Ordering::Relaxed)).collect();
+        let total: u64 = counts.iter().sum();
+
+        let mut p99_jitter_ns = 0;
+        if total > 0 {
+            let rank = total - total / 100;
+            let mut seen = 0;
+            for (idx, count) in counts.iter().enumerate() {
+                seen += count;
+                if seen >= rank {
+                    p99_jitter_ns = bucket_ceiling_us(idx) * 1_000;
+                    break;
+                }
+            }
+        }
+
+        let min = self.min_interval_ns.load(Ordering::Relaxed);
+        TelemetrySnapshot {
+            packets_sent: self.packets_sent.load(Ordering::Relaxed),
+            send_errors: self.send_errors.load(Ordering::Relaxed),
+            missed_deadlines: self.missed_deadlines.load(Ordering::Relaxed),
+            samples,
+            min_interval_ns: if min == u64::MAX { 0 } else { min },
+            max_interval_ns: self.max_interval_ns.load(Ordering::Relaxed),
+            mean_interval_ns: self.sum_interval_ns.load(Ordering::Relaxed) / samples.max(1),
+            p99_jitter_ns,
+            target_interval_ns,
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! How the engine thread waits between iterations.
+//!
+//! `Hybrid` sleeps on an absolute `CLOCK_MONOTONIC` deadline for most of the
+//! gap and spins only for the final margin, which absorbs the kernel's wakeup
+//! latency without burning a core. `Spin` keeps the original busy loop.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+/// Default spin margin: enough to cover a typical SCHED_FIFO wakeup.
+pub const DEFAULT_SPIN_MARGIN_NS: u64 = 50_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitStrategy {
+    /// Busy-wait the whole interval (lowest jitter, one core at 100%).
+    Spin,
+    /// Sleep until `spin_margin_ns` before the deadline, then spin.
+    Hybrid { spin_margin_ns: u64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerConfig {
+    pub strategy: WaitStrategy,
+    /// Park the engine thread entirely while the client is idle; any state
+    /// or config change wakes it.
+    pub block_when_idle: bool,
+}
+
+impl Default for TimerConfig {
+    fn default() -> Self {
+        Self {
+            strategy: WaitStrategy::Hybrid { spin_margin_ns: DEFAULT_SPIN_MARGIN_NS },
+            block_when_idle: true,
+        }
+    }
+}
+
+/// Lock-free holder for a `TimerConfig`; `u64::MAX` as margin means `Spin`.
+pub(crate) struct TimerCell {
+    spin_margin_ns: AtomicU64,
+    block_when_idle: AtomicBool,
+}
+
+impl TimerCell {
+    pub fn new(config: TimerConfig) -> Self {
+        let cell = Self {
+            spin_margin_ns: AtomicU64::new(0),
+            block_when_idle: AtomicBool::new(false),
+        };
+        cell.store(config);
+        cell
+    }
+
+    pub fn load(&self) -> TimerConfig {
+        let strategy = match self.spin_margin_ns.load(Ordering::Relaxed) {
+            u64::MAX => WaitStrategy::Spin,
+            spin_margin_ns => WaitStrategy::Hybrid { spin_margin_ns },
+        };
+        TimerConfig {
+            strategy,
+            block_when_idle: self.block_when_idle.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn store(&self, config: TimerConfig) {
+        let raw = match config.strategy {
+            WaitStrategy::Spin => u64::MAX,
+            WaitStrategy::Hybrid { spin_margin_ns } => spin_margin_ns.min(u64::MAX - 1),
+        };
+        self.spin_margin_ns.store(raw, Ordering::Relaxed);
+        self.block_when_idle.store(config.block_when_idle, Ordering::Relaxed);
+    }
+}
+
+/// Wakes a parked engine thread. Waiters remember the generation they saw
+/// before checking their condition, so a notify in between is never lost.
+pub(crate) struct Waker {
+    generation: Mutex<u64>,
+    cond: Condvar,
+}
+
+impl Waker {
+    pub fn new() -> Self {
+        Self {
+            generation: Mutex::new(0),
+            cond: Condvar::new(),
+        }
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.lock().map(|g| *g).unwrap_or(0)
+    }
+
+    pub fn notify(&self) {
+        if let Ok(mut g) = self.generation.lock() {
+            *g = g.wrapping_add(1);
+        }
+        self.cond.notify_all();
+    }
+
+    /// Blocks until `notify` is called after `seen` was read.
+    pub fn wait_changed(&self, seen: u64) {
+        if let Ok(guard) = self.generation.lock() {
+            drop(self.cond.wait_while(guard, |g| *g == seen));
+        }
+    }
+
+    /// Like `wait_changed`, but gives up after `timeout`. Used in place of a
+    /// plain sleep so shutdown never waits out the full period.
+    pub fn wait_changed_timeout(&self, seen: u64, timeout: Duration) {
+        if let Ok(guard) = self.generation.lock() {
+            drop(self.cond.wait_timeout_while(guard, timeout, |g| *g == seen));
+        }
+    }
+}
+
+/// Nanoseconds on `CLOCK_MONOTONIC`, the clock `wait_until` sleeps on.
+pub fn monotonic_ns() -> u64 {
+    let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts);
+    }
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+/// Waits until `deadline_ns` (on `monotonic_ns`'s clock) per `strategy`.
+/// `Spin` returns after a single spin hint, matching the original loop.
+pub fn wait_until(strategy: WaitStrategy, deadline_ns: u64) {
+    let spin_margin_ns = match strategy {
+        WaitStrategy::Spin => {
+            std::hint::spin_loop();
+            return;
+        }
+        WaitStrategy::Hybrid { spin_margin_ns } => spin_margin_ns,
+    };
+
+    let now = monotonic_ns();
+    if deadline_ns > now + spin_margin_ns {
+        let wake = deadline_ns - spin_margin_ns;
+        let ts = libc::timespec {
+            tv_sec: (wake / 1_000_000_000) as libc::time_t,
+            tv_nsec: (wake % 1_000_000_000) as libc::c_long,
+        };
+        // EINTR just means we wake early and spin a bit longer.
+        unsafe {
+            libc::clock_nanosleep(libc::CLOCK_MONOTONIC, libc::TIMER_ABSTIME, &ts, std::ptr::null_mut());
+        }
+    }
+    while monotonic_ns() < deadline_ns {
+        std::hint::spin_loop();
+    }
+}
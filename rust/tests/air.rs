@@ -0,0 +1,182 @@
+use rustnithm::air::{AirGeometry, AirTracker, FlickConfig, FlickDetector, FlickDirection, MAX_POINTERS};
+use FlickDirection::{Down, Up};
+
+/// 600 high, so every zone is 100 high; zone 1 spans y 500..600.
+const GEOMETRY: AirGeometry = AirGeometry { height: 600.0, pair_ratio: 0.2 };
+
+fn tracker() -> AirTracker {
+    let mut tracker = AirTracker::new();
+    tracker.set_geometry(Some(GEOMETRY));
+    tracker
+}
+
+#[test]
+fn heights_map_to_zones_from_the_bottom_up() {
+    assert_eq!(GEOMETRY.zones_at(550.0), 0b00_0001);
+    assert_eq!(GEOMETRY.zones_at(450.0), 0b00_0010);
+    assert_eq!(GEOMETRY.zones_at(50.0), 0b10_0000);
+    assert_eq!(GEOMETRY.zones_at(600.0), 0b00_0001);
+    assert_eq!(GEOMETRY.zones_at(0.0), 0b10_0000);
+    // Outside the air area, e.g. down on the slider.
+    assert_eq!(GEOMETRY.zones_at(601.0), 0);
+    assert_eq!(GEOMETRY.zones_at(-1.0), 0);
+}
+
+#[test]
+fn zone_edges_also_light_the_neighbour() {
+    // Within 20 of the border between zones 2 and 3 (y = 400).
+    assert_eq!(GEOMETRY.zones_at(385.0), 0b00_0110);
+    assert_eq!(GEOMETRY.zones_at(415.0), 0b00_0110);
+    assert_eq!(GEOMETRY.zones_at(425.0), 0b00_0010);
+    // The outer edges have no neighbour.
+    assert_eq!(GEOMETRY.zones_at(595.0), 0b00_0001);
+    assert_eq!(GEOMETRY.zones_at(5.0), 0b10_0000);
+
+    let wide = AirGeometry { pair_ratio: 2.0, ..GEOMETRY };
+    assert_eq!(wide.zones_at(440.0), 0b00_0110, "clamped to half a zone");
+    assert_eq!(wide.zones_at(460.0), 0b00_0011);
+    let none = AirGeometry { pair_ratio: 0.0, ..GEOMETRY };
+    assert_eq!(none.zones_at(401.0), 0b00_0010);
+}
+
+#[test]
+fn pointers_are_tracked_independently() {
+    let mut tracker = tracker();
+    tracker.down(7, 550.0);
+    tracker.down(9, 50.0);
+    assert_eq!(tracker.air_byte(), 0b10_0001);
+
+    tracker.move_to(7, 250.0);
+    assert_eq!(tracker.air_byte(), 0b10_1000);
+
+    tracker.up(9);
+    assert_eq!(tracker.air_byte(), 0b00_1000);
+    tracker.up(9);
+    tracker.up(7);
+    assert_eq!((tracker.air_byte(), tracker.pointers()), (0, 0));
+
+    // A move for an unknown pointer starts tracking it.
+    tracker.move_to(3, 150.0);
+    assert_eq!(tracker.air_byte(), 0b01_0000);
+    tracker.clear();
+    assert_eq!(tracker.pointers(), 0);
+}
+
+#[test]
+fn extra_pointers_are_ignored_until_one_lifts() {
+    let mut tracker = tracker();
+    for pid in 0..MAX_POINTERS as i32 {
+        tracker.down(pid, 550.0);
+    }
+    tracker.down(100, 50.0);
+    assert_eq!(tracker.air_byte(), 0b00_0001);
+
+    tracker.up(0);
+    tracker.down(100, 50.0);
+    assert_eq!(tracker.air_byte(), 0b10_0001);
+}
+
+#[test]
+fn no_geometry_means_no_native_air() {
+    let mut tracker = AirTracker::new();
+    tracker.down(1, 550.0);
+    assert!(!tracker.is_active());
+    assert_eq!(tracker.air_byte(), 0);
+
+    tracker.set_geometry(Some(AirGeometry { height: 0.0, pair_ratio: 0.2 }));
+    assert!(!tracker.is_active());
+    tracker.set_geometry(Some(GEOMETRY));
+    assert_eq!(tracker.air_byte(), 0b00_0001);
+}
+
+/// Touch traces as `(ms, y)`, captured at 120 Hz on a 2400 px high screen.
+/// The first sample is the touch-down.
+const QUICK_FLICK_UP: &[(u64, f32)] = &[
+    (0, 900.0), (8, 898.0), (16, 880.0), (25, 850.0), (33, 812.0), (41, 790.0), (50, 786.0),
+];
+const SLOW_DRAG_UP: &[(u64, f32)] = &[
+    (0, 900.0), (100, 880.0), (200, 860.0), (300, 840.0), (400, 820.0), (500, 800.0), (600, 780.0),
+];
+const SHORT_TAP_JITTER: &[(u64, f32)] = &[
+    (0, 700.0), (8, 703.0), (16, 698.0), (25, 702.0), (33, 699.0), (41, 701.0),
+];
+/// Up, back down, up again, like three air notes in a row.
+const ZIGZAG: &[(u64, f32)] = &[
+    (0, 900.0), (8, 870.0), (16, 830.0), (25, 800.0), (33, 830.0), (41, 870.0), (50, 905.0),
+    (58, 880.0), (66, 840.0), (75, 800.0),
+];
+/// Rests for a while, then flicks: the rest must not dilute the velocity.
+const HOLD_THEN_FLICK: &[(u64, f32)] = &[
+    (0, 900.0), (8, 899.0), (100, 899.0), (200, 898.0), (300, 898.0), (308, 870.0), (316, 835.0),
+];
+/// Keeps going up after the flick fired: still one flick.
+const LONG_SWIPE_UP: &[(u64, f32)] = &[
+    (0, 1200.0), (8, 1170.0), (16, 1130.0), (25, 1080.0), (33, 1020.0), (41, 950.0), (50, 880.0),
+    (58, 820.0),
+];
+
+/// Replays `trace` for pointer `pid` and returns when (in ms) each flick fired.
+fn replay(detector: &mut FlickDetector, pid: i32, trace: &[(u64, f32)]) -> Vec<(u64, FlickDirection)> {
+    let ns = |ms: u64| ms * 1_000_000;
+    let (&(t0, y0), moves) = trace.split_first().unwrap();
+    detector.down(pid, y0, ns(t0));
+    let fired = moves
+        .iter()
+        .filter_map(|&(t, y)| detector.on_move(pid, y, ns(t)).map(|d| (t, d)))
+        .collect();
+    detector.up(pid);
+    fired
+}
+
+fn detector() -> FlickDetector {
+    let mut detector = FlickDetector::new();
+    detector.set_config(Some(FlickConfig::default()));
+    detector
+}
+
+#[test]
+fn recorded_traces_fire_where_expected() {
+    let mut detector = detector();
+    assert_eq!(replay(&mut detector, 1, QUICK_FLICK_UP), [(25, Up)]);
+    assert_eq!(replay(&mut detector, 1, SLOW_DRAG_UP), []);
+    assert_eq!(replay(&mut detector, 1, SHORT_TAP_JITTER), []);
+    assert_eq!(replay(&mut detector, 1, ZIGZAG), [(16, Up), (41, Down), (66, Up)]);
+    assert_eq!(replay(&mut detector, 1, HOLD_THEN_FLICK), [(316, Up)]);
+    assert_eq!(replay(&mut detector, 1, LONG_SWIPE_UP), [(16, Up)]);
+}
+
+#[test]
+fn thresholds_are_configurable() {
+    let mut detector = FlickDetector::new();
+    assert_eq!(replay(&mut detector, 1, QUICK_FLICK_UP), [], "off by default");
+
+    // Far enough, but the drag is slower than 800 px/s.
+    detector.set_config(Some(FlickConfig { min_distance: 100.0, ..FlickConfig::default() }));
+    assert_eq!(replay(&mut detector, 1, SLOW_DRAG_UP), []);
+    detector.set_config(Some(FlickConfig { min_distance: 100.0, min_velocity: 150.0, max_duration_ns: 1_000_000_000, air_down: false }));
+    assert_eq!(replay(&mut detector, 1, SLOW_DRAG_UP), [(500, Up)]);
+    // The same drag over a window too short to cover the distance.
+    detector.set_config(Some(FlickConfig { max_duration_ns: 300_000_000, min_distance: 100.0, min_velocity: 150.0, ..FlickConfig::default() }));
+    assert_eq!(replay(&mut detector, 1, SLOW_DRAG_UP), []);
+
+    detector.set_config(Some(FlickConfig { min_distance: 4.0, min_velocity: 100.0, ..FlickConfig::default() }));
+    assert_eq!(replay(&mut detector, 1, SHORT_TAP_JITTER), [(16, Up), (25, Down)], "jitter above a tiny distance");
+}
+
+#[test]
+fn pointers_flick_independently() {
+    let mut detector = detector();
+    let ns = |ms: u64| ms * 1_000_000;
+    detector.down(1, 900.0, 0);
+    detector.down(2, 500.0, 0);
+    assert_eq!(detector.on_move(1, 870.0, ns(8)), None);
+    assert_eq!(detector.on_move(2, 530.0, ns(8)), None);
+    assert_eq!(detector.on_move(1, 840.0, ns(16)), Some(Up));
+    assert_eq!(detector.on_move(2, 560.0, ns(16)), Some(Down));
+
+    // A move without a down starts the pointer there.
+    assert_eq!(detector.on_move(3, 900.0, ns(16)), None);
+    assert_eq!(detector.on_move(3, 800.0, ns(24)), Some(Up));
+    detector.clear();
+    assert_eq!(detector.on_move(1, 700.0, ns(30)), None);
+}
@@ -0,0 +1,64 @@
+use rustnithm::auth::{open, seal, AuthError, Direction, ReplayWindow, AUTH_LEN};
+use rustnithm::protocol::{ClientPacket, Transport};
+
+const KEY: &[u8] = b"correct horse battery staple";
+const NONCE: u64 = 0x5EED;
+
+fn sealed_buttons(counter: u64) -> Vec<u8> {
+    let plain = ClientPacket::Buttons(0b101).encode(Transport::Udp);
+    seal(KEY, Direction::ToServer, NONCE, counter, plain.as_bytes()).as_bytes().to_vec()
+}
+
+#[test]
+fn sealed_frame_opens_to_the_plain_frame() {
+    let plain = ClientPacket::Buttons(0b101).encode(Transport::Udp);
+    let sealed = sealed_buttons(42);
+    assert_eq!(sealed.len(), plain.as_bytes().len() + AUTH_LEN);
+    assert_eq!(sealed[0], plain.as_bytes()[0] | 0x01);
+    assert_eq!(&sealed[2..10], &42u64.to_le_bytes());
+
+    let (opened, counter) = open(KEY, Direction::ToServer, NONCE, &sealed).unwrap();
+    assert_eq!(opened.as_bytes(), plain.as_bytes());
+    assert_eq!(counter, 42);
+}
+
+#[test]
+fn tampered_or_foreign_frames_are_rejected() {
+    let sealed = sealed_buttons(7);
+
+    let mut flipped = sealed.clone();
+    flipped[1] ^= 0x02;
+    assert_eq!(open(KEY, Direction::ToServer, NONCE, &flipped).unwrap_err(), AuthError::BadTag);
+
+    let mut recounted = sealed.clone();
+    recounted[2] = 8;
+    assert_eq!(open(KEY, Direction::ToServer, NONCE, &recounted).unwrap_err(), AuthError::BadTag);
+
+    assert_eq!(open(b"other key", Direction::ToServer, NONCE, &sealed).unwrap_err(), AuthError::BadTag);
+    // Reflected back at the client.
+    assert_eq!(open(KEY, Direction::ToClient, NONCE, &sealed).unwrap_err(), AuthError::BadTag);
+    // Recorded in an earlier session.
+    assert_eq!(open(KEY, Direction::ToServer, NONCE + 1, &sealed).unwrap_err(), AuthError::BadTag);
+
+    let plain = ClientPacket::Buttons(0b101).encode(Transport::Udp);
+    assert_eq!(open(KEY, Direction::ToServer, NONCE, plain.as_bytes()).unwrap_err(), AuthError::Unsealed);
+    assert_eq!(open(KEY, Direction::ToServer, NONCE, &sealed[..AUTH_LEN]).unwrap_err(), AuthError::Length);
+    assert_eq!(open(KEY, Direction::ToServer, NONCE, &[]).unwrap_err(), AuthError::Length);
+}
+
+#[test]
+fn replay_window_accepts_each_counter_once() {
+    let mut window = ReplayWindow::default();
+    assert!(window.accept(100));
+    assert!(!window.accept(100));
+    // Late but inside the window.
+    assert!(window.accept(98));
+    assert!(!window.accept(98));
+    assert!(window.accept(101));
+    assert!(window.accept(99));
+    // Too old once the window moved past it.
+    assert!(window.accept(200));
+    assert!(!window.accept(136));
+    assert!(window.accept(137));
+    assert!(!window.accept(137));
+}
@@ -0,0 +1,103 @@
+use rustnithm::brokenithm::{decode_client, decode_server, encode_server, split_frame, ClientFrame, Session};
+use rustnithm::protocol::{ClientPacket, LedFrame, ProtocolError, ServerPacket};
+
+const CARD: [u8; 10] = [0x01, 0x23, 0x45, 0x67, 0x89, 0x01, 0x23, 0x45, 0x67, 0x89];
+
+fn frames(session: &mut Session, packet: ClientPacket) -> Vec<Vec<u8>> {
+    session.encode(&packet).iter().map(<[u8]>::to_vec).collect()
+}
+
+#[test]
+fn input_frame_matches_golden_bytes() {
+    let mut session = Session::new();
+    let out = frames(&mut session, ClientPacket::FullState { buttons: 0b110, air: 0b10_0001, slider: 0x8000_0001 });
+    assert_eq!(out.len(), 1);
+    let mut expected = vec![43, b'I', b'N', b'P', 1, 0, 0, 0, 0, 1, 0x80];
+    expected.extend([0; 30]);
+    expected.extend([0x80, 1, 1]);
+    assert_eq!(out[0], expected);
+    assert_eq!(
+        decode_client(&expected),
+        Ok(ClientFrame::Input { air: 0b10_0001, slider: 0x8000_0001, test: true, service: true })
+    );
+
+    // Nothing to send for the packets Brokenithm has no form of.
+    assert!(session.encode(&ClientPacket::Sync { target_connected: true }).is_empty());
+}
+
+#[test]
+fn coin_is_sent_once_per_press() {
+    let mut session = Session::new();
+    let pressed = ClientPacket::FullState { buttons: 0b1, air: 0, slider: 0 };
+    let released = ClientPacket::FullState { buttons: 0, air: 0, slider: 0 };
+
+    let out = frames(&mut session, pressed);
+    assert_eq!(out[0], vec![4, b'F', b'N', b'C', 1]);
+    assert_eq!(decode_client(&out[0]), Ok(ClientFrame::Function(1)));
+    assert_eq!(out.len(), 2);
+    assert_eq!(frames(&mut session, pressed).len(), 1);
+    assert_eq!(frames(&mut session, released).len(), 1);
+    assert_eq!(frames(&mut session, pressed).len(), 2);
+}
+
+#[test]
+fn card_is_released_when_input_resumes() {
+    let mut session = Session::new();
+    let out = frames(&mut session, ClientPacket::Card(CARD));
+    let expected = [&[15, b'C', b'R', b'D', 1, 0][..], &CARD[..]].concat();
+    assert_eq!(out, vec![expected.clone()]);
+    assert_eq!(decode_client(&expected), Ok(ClientFrame::Card(Some(CARD))));
+
+    // Card taken away with coin pressed: release first, coin on the next tick.
+    let coin = ClientPacket::FullState { buttons: 0b1, air: 0, slider: 0 };
+    let out = frames(&mut session, coin);
+    assert_eq!(out.len(), 2);
+    assert_eq!(decode_client(&out[0]), Ok(ClientFrame::Card(None)));
+    let out = frames(&mut session, coin);
+    assert_eq!(decode_client(&out[0]), Ok(ClientFrame::Function(1)));
+    assert_eq!(frames(&mut session, coin).len(), 1);
+}
+
+#[test]
+fn ping_and_pong_carry_id_and_time() {
+    let mut session = Session::new();
+    let out = frames(&mut session, ClientPacket::Ping { id: 0x0102, micros: 0x0304_0506 });
+    assert_eq!(out, vec![vec![11, b'P', b'I', b'N', 0x06, 0x05, 0x04, 0x03, 0x02, 0x01, 0, 0]]);
+
+    let pong = ServerPacket::Pong { id: 0x0102, micros: 0x0304_0506 };
+    let bytes = encode_server(&pong).unwrap();
+    assert_eq!(bytes, vec![11, b'P', b'O', b'N', 0x06, 0x05, 0x04, 0x03, 0x02, 0x01, 0, 0]);
+    assert_eq!(decode_server(&bytes), Ok(pong));
+}
+
+#[test]
+fn led_frames_are_brg_right_to_left() {
+    let mut frame = LedFrame::default();
+    frame.slider[0] = [0x11, 0x22, 0x33];
+    frame.slider[30] = [0xAA, 0xBB, 0xCC];
+    let bytes = encode_server(&ServerPacket::Led(frame)).unwrap();
+    assert_eq!(bytes.len(), 100);
+    assert_eq!(&bytes[..4], &[99, b'L', b'E', b'D']);
+    assert_eq!(&bytes[4..7], &[0xCC, 0xAA, 0xBB]);
+    assert_eq!(&bytes[94..97], &[0x33, 0x11, 0x22]);
+    assert_eq!(decode_server(&bytes), Ok(ServerPacket::Led(frame)));
+    assert_eq!(encode_server(&ServerPacket::SyncConfirm { connected: true }), None);
+}
+
+#[test]
+fn malformed_frames_are_rejected() {
+    assert_eq!(decode_server(&[]), Err(ProtocolError::Empty));
+    assert!(matches!(decode_server(&[5, b'L', b'E', b'D']), Err(ProtocolError::Length { .. })));
+    assert!(matches!(decode_server(&[3, b'L', b'E', b'D']), Err(ProtocolError::Length { .. })));
+    assert_eq!(decode_server(&[3, b'D', b'I', b'S']), Err(ProtocolError::UnknownHeader(b'D')));
+    assert!(matches!(decode_client(&[4, b'I', b'N', b'P', 0]), Err(ProtocolError::Length { .. })));
+    assert!(matches!(decode_client(&[1, b'I']), Err(ProtocolError::Length { .. })));
+}
+
+#[test]
+fn tcp_frames_split_on_their_size_byte() {
+    assert_eq!(split_frame(&[]), None);
+    assert_eq!(split_frame(&[4, b'F', b'N', b'C']), None);
+    let stream = [4, b'F', b'N', b'C', 1, 11, b'P'];
+    assert_eq!(split_frame(&stream), Some((&stream[..5], 5)));
+}
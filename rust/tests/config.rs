@@ -0,0 +1,84 @@
+use std::net::{SocketAddr, UdpSocket};
+use rustnithm::config::{parse_target, resolve_target};
+use rustnithm::{ConfigError, Engine};
+
+fn target() -> SocketAddr {
+    "127.0.0.1:9".parse().unwrap()
+}
+
+#[test]
+fn rejects_invalid_addresses() {
+    assert_eq!(parse_target("192.168.1.10", 8200).unwrap(), "192.168.1.10:8200".parse().unwrap());
+    for (ip, port) in [("not an ip!", 8200), ("300.1.1.1", 8200), ("10.0.0.1", 70000), ("10.0.0.1", -1)] {
+        assert!(
+            matches!(parse_target(ip, port), Err(ConfigError::InvalidAddress(_))),
+            "{ip}:{port}"
+        );
+    }
+}
+
+#[test]
+fn rejects_configure_before_start() {
+    let engine = Engine::new();
+    assert!(matches!(engine.configure(target(), 0), Err(ConfigError::NotInitialized)));
+
+    engine.start(1000);
+    engine.configure(target(), 0).unwrap();
+    engine.shutdown();
+    assert!(matches!(engine.configure(target(), 0), Err(ConfigError::NotInitialized)));
+}
+
+#[test]
+fn rejects_unsupported_protocol() {
+    let engine = Engine::new();
+    engine.start(1000);
+    assert!(matches!(engine.configure(target(), 2), Err(ConfigError::UnsupportedProtocol(2))));
+    engine.configure(target(), 1).unwrap();
+}
+
+#[test]
+fn reports_bind_failure() {
+    let taken = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let engine = Engine::new();
+    engine.start(1000);
+    engine.set_bind_addr(Some(taken.local_addr().unwrap()));
+    assert!(matches!(engine.configure(target(), 0), Err(ConfigError::Bind(_))));
+
+    engine.set_bind_addr(None);
+    engine.configure(target(), 0).unwrap();
+}
+
+#[test]
+fn parses_ipv6_literals() {
+    let expected: SocketAddr = "[fe80::1]:8200".parse().unwrap();
+    for host in ["fe80::1", "[fe80::1]", "[fe80::1]:8200", " fe80::1 "] {
+        assert_eq!(parse_target(host, 8200).unwrap(), expected, "{host}");
+    }
+    // An embedded port wins over the separate one.
+    assert_eq!(parse_target("[::1]:9000", 8200).unwrap(), "[::1]:9000".parse().unwrap());
+}
+
+#[test]
+fn resolves_hostnames() {
+    let addr = resolve_target("localhost", 8200).unwrap();
+    assert!(addr.ip().is_loopback());
+    assert_eq!(addr.port(), 8200);
+
+    assert!(matches!(parse_target("localhost", 8200), Err(ConfigError::InvalidAddress(_))));
+    assert!(matches!(resolve_target("no-such-host.invalid", 8200), Err(ConfigError::UnresolvedHost(_))));
+    assert!(matches!(resolve_target("bad host!", 8200), Err(ConfigError::InvalidAddress(_))));
+    assert!(matches!(resolve_target("300.1.1.1", 8200), Err(ConfigError::InvalidAddress(_))));
+}
+
+#[test]
+fn binds_socket_matching_target_family() {
+    // Skip on hosts without IPv6.
+    if UdpSocket::bind("[::1]:0").is_err() {
+        return;
+    }
+    let engine = Engine::new();
+    engine.start(1000);
+    assert_eq!(engine.configure_host("::1", 9, 0).unwrap(), "[::1]:9".parse().unwrap());
+    assert_eq!(engine.configure_host("[::1]", 9, 1).unwrap(), "[::1]:9".parse().unwrap());
+    assert!(matches!(engine.configure_host("::1", 9, 5), Err(ConfigError::UnsupportedProtocol(5))));
+}
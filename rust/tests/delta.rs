@@ -0,0 +1,48 @@
+use rustnithm::delta::{DeltaGate, SendMode};
+use rustnithm::protocol::ClientPacket;
+
+const TICK: u64 = 1_000_000; // 1000 Hz
+const MODE: SendMode = SendMode::Delta { repeats: 3, keepalive_ns: 100 * TICK };
+
+fn slider(mask: u32) -> ClientPacket {
+    ClientPacket::AirSlider { air: 0, slider: mask }
+}
+
+/// Runs `ticks` ticks of an unchanging `packet` starting at `start` and
+/// returns the ticks (relative to `start`) that were sent.
+fn run(gate: &mut DeltaGate, packet: ClientPacket, start: u64, ticks: u64) -> Vec<u64> {
+    (0..ticks)
+        .filter(|&t| gate.admit(MODE, &packet, (start + t) * TICK))
+        .collect()
+}
+
+#[test]
+fn continuous_sends_every_tick() {
+    let mut gate = DeltaGate::new();
+    assert!((0..1000).all(|t| gate.admit(SendMode::Continuous, &slider(1), t * TICK)));
+}
+
+#[test]
+fn change_is_repeated_then_kept_alive() {
+    let mut gate = DeltaGate::new();
+    let sent = run(&mut gate, slider(1), 0, 350);
+    assert_eq!(sent, vec![0, 1, 2, 3, 103, 203, 303]);
+}
+
+#[test]
+fn change_goes_out_on_the_next_tick() {
+    let mut gate = DeltaGate::new();
+    run(&mut gate, slider(1), 0, 50);
+    // Mid-keepalive change: sent right away, with a fresh set of repeats.
+    assert_eq!(run(&mut gate, slider(3), 50, 10), vec![0, 1, 2, 3]);
+    // Flipping back counts as a change too.
+    assert_eq!(run(&mut gate, slider(1), 60, 2), vec![0, 1]);
+}
+
+#[test]
+fn reset_resends_unchanged_input() {
+    let mut gate = DeltaGate::new();
+    run(&mut gate, slider(1), 0, 50);
+    gate.reset();
+    assert_eq!(run(&mut gate, slider(1), 50, 10), vec![0, 1, 2, 3]);
+}
@@ -0,0 +1,65 @@
+use std::io;
+use std::time::{Duration, Instant};
+use rustnithm::discovery::{discover, probe, Announce, Responder, Transports, MAX_NAME_LEN};
+use rustnithm::protocol::{ProtocolError, PROTOCOL_VERSION};
+
+fn announce(name: &str, port: u16, transports: Transports) -> Announce {
+    Announce { name: name.into(), port, transports, version: PROTOCOL_VERSION }
+}
+
+#[test]
+fn announce_round_trips() {
+    let a = announce("cab 1", 8200, Transports::BOTH);
+    let bytes = a.encode();
+    assert_eq!(&bytes[..8], &[b'R', b'N', b'!', PROTOCOL_VERSION, 0b11, 0x08, 0x20, 5]);
+    assert_eq!(Announce::decode(&bytes).unwrap(), a);
+    assert_eq!(&probe()[..3], b"RN?");
+}
+
+#[test]
+fn long_names_are_cut_on_a_char_boundary() {
+    let name = "é".repeat(MAX_NAME_LEN);
+    let decoded = Announce::decode(&announce(&name, 1, Transports::UDP).encode()).unwrap();
+    assert_eq!(decoded.name, "é".repeat(MAX_NAME_LEN / 2));
+}
+
+#[test]
+fn malformed_announces_are_rejected() {
+    let bytes = announce("x", 1, Transports::TCP).encode();
+    assert!(matches!(Announce::decode(&bytes[..7]), Err(ProtocolError::Length { .. })));
+    assert!(matches!(Announce::decode(&bytes[..8]), Err(ProtocolError::Length { .. })));
+    let mut wrong = bytes.clone();
+    wrong[2] = b'?';
+    assert_eq!(Announce::decode(&wrong), Err(ProtocolError::UnknownHeader(b'R')));
+}
+
+#[test]
+fn discover_collects_each_responder_once() {
+    let any = "127.0.0.1:0".parse().unwrap();
+    let first = Responder::bind(any, announce("first", 8200, Transports::BOTH)).unwrap();
+    let second = Responder::bind(any, announce("second", 9000, Transports::UDP)).unwrap();
+    // Nobody listens here (discard port); it must not stop the others.
+    let silent = "127.0.0.1:9".parse().unwrap();
+    let targets = [first.local_addr(), silent, second.local_addr(), first.local_addr()];
+
+    let started = Instant::now();
+    let mut servers = discover(&targets, Duration::from_millis(200)).unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(200));
+    servers.sort_by(|a, b| a.name.cmp(&b.name));
+
+    assert_eq!(servers.len(), 2);
+    assert_eq!(servers[0].name, "first");
+    assert_eq!(servers[0].addr, "127.0.0.1:8200".parse().unwrap());
+    assert_eq!(servers[0].transports, Transports::BOTH);
+    assert_eq!(servers[1].name, "second");
+    assert_eq!(servers[1].addr.port(), 9000);
+    assert!(servers[1].transports.contains(Transports::UDP));
+    assert!(!servers[1].transports.contains(Transports::TCP));
+}
+
+#[test]
+fn discover_rejects_mixed_address_families() {
+    let targets = ["127.0.0.1:9".parse().unwrap(), "[::1]:9".parse().unwrap()];
+    let err = discover(&targets, Duration::from_millis(50)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
@@ -0,0 +1,610 @@
+use std::net::UdpSocket;
+use std::thread;
+use std::time::{Duration, Instant};
+use rustnithm::air::{AirGeometry, FlickConfig};
+use rustnithm::auth::{open, seal, Direction};
+use rustnithm::loopback::{InputView, LoopbackServer};
+use rustnithm::delta::SendMode;
+use rustnithm::protocol::{ClientPacket, Features, LedFrame, ServerPacket, Transport, PROTOCOL_VERSION};
+use rustnithm::{ConnState, Dialect, Engine, InputUpdate};
+
+fn wait_for(server: &LoopbackServer, pred: impl Fn(&InputView) -> bool) -> InputView {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let view = server.snapshot();
+        if pred(&view) || Instant::now() > deadline {
+            return view;
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+/// Waits for the hello ack.
+fn negotiated(engine: &Engine) -> Option<(u8, Features)> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while engine.negotiated().is_none() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(5));
+    }
+    engine.negotiated()
+}
+
+/// Requests a sync until the server confirms it. Over TCP the first request
+/// may time out while the engine is still connecting, just like a user tap.
+fn sync_on(engine: &Engine) -> ConnState {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while engine.state() != ConnState::Connected && Instant::now() < deadline {
+        if engine.state() == ConnState::Idle {
+            assert_eq!(engine.toggle_sync(), Ok(ConnState::Syncing { target_connected: true }));
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+    engine.state()
+}
+
+fn run_session(protocol_type: u32, transport: Transport) {
+    let server = LoopbackServer::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let engine = Engine::new();
+    engine.start(1000);
+    engine.configure(server.local_addr(), protocol_type).unwrap();
+
+    assert_eq!(sync_on(&engine), ConnState::Connected);
+
+    engine.update_state(&InputUpdate {
+        packet_type: 32,
+        air_byte: 0b10_0001,
+        slider_mask: 0x8000_0001,
+        air_mode: 1,
+        ..Default::default()
+    });
+    let view = wait_for(&server, |v| v.slider == 0x8000_0001);
+    assert!(view.connected);
+    assert_eq!(view.air, 0b10_0001);
+    assert_eq!(view.last_transport, Some(transport));
+    assert_eq!(view.bad_frames, 0);
+
+    let card = [0x12; 10];
+    engine.update_state(&InputUpdate {
+        packet_type: 48,
+        air_mode: 1,
+        card_bcd: Some(card),
+        ..Default::default()
+    });
+    assert_eq!(wait_for(&server, |v| v.card.is_some()).card, Some(card));
+
+    let telemetry = engine.telemetry();
+    assert!(telemetry.packets_sent >= view.packets);
+    assert!(telemetry.samples > 0);
+    assert_eq!(telemetry.target_interval_ns, 1_000_000);
+}
+
+#[test]
+fn udp_session_against_loopback() {
+    run_session(0, Transport::Udp);
+}
+
+#[test]
+fn tcp_session_against_loopback() {
+    run_session(1, Transport::Tcp);
+}
+
+#[test]
+fn stamped_session_has_no_gaps() {
+    for (protocol_type, transport) in [(0, Transport::Udp), (1, Transport::Tcp)] {
+        let server = LoopbackServer::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let engine = Engine::new();
+        engine.start(1000);
+        engine.configure(server.local_addr(), protocol_type).unwrap();
+        assert_eq!(sync_on(&engine), ConnState::Connected);
+        assert!(negotiated(&engine).is_some());
+
+        // Packets sent before the ack are plain; everything after is stamped.
+        thread::sleep(Duration::from_millis(20));
+        let before = server.snapshot();
+        let view = wait_for(&server, |v| v.stamped >= before.stamped + 100);
+        assert_eq!(view.stamped - before.stamped, view.packets - before.packets, "{transport:?}");
+        assert_eq!(view.bad_frames, 0);
+        assert_eq!(view.reordered, 0);
+        if transport == Transport::Tcp {
+            // Over loopback TCP nothing is lost; UDP may drop a sync retry.
+            assert_eq!(view.lost, 0);
+        }
+        assert!(view.last_stamp.is_some());
+    }
+}
+
+#[test]
+fn full_state_carries_every_input_group() {
+    let server = LoopbackServer::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let engine = Engine::new();
+    engine.start(1000);
+    engine.configure(server.local_addr(), 0).unwrap();
+    assert_eq!(sync_on(&engine), ConnState::Connected);
+
+    // Buttons win the packet type, yet air and slider still go out with them.
+    engine.update_state(&InputUpdate {
+        packet_type: 16,
+        button_mask: 0b101,
+        air_byte: 0b10_0001,
+        slider_mask: 0x8000_0001,
+        air_mode: 1,
+        ..Default::default()
+    });
+    let view = wait_for(&server, |v| v.buttons == 0b101 && v.slider != 0);
+    assert_eq!(view.air, 0b10_0001);
+    assert_eq!(view.slider, 0x8000_0001);
+    assert_eq!(view.bad_frames, 0);
+}
+
+#[test]
+fn native_air_follows_tracked_pointers() {
+    let server = LoopbackServer::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let engine = Engine::new();
+    engine.start(1000);
+    engine.configure(server.local_addr(), 0).unwrap();
+    assert_eq!(sync_on(&engine), ConnState::Connected);
+    engine.set_air_geometry(Some(AirGeometry { height: 600.0, pair_ratio: 0.0 }));
+
+    let update = InputUpdate { packet_type: 32, air_byte: 0b11_1111, slider_mask: 1, air_mode: 1, ..Default::default() };
+    engine.update_state(&update);
+    engine.touch_down(4, 550.0);
+    engine.touch_down(5, 50.0);
+    assert_eq!(wait_for(&server, |v| v.air == 0b10_0001).air, 0b10_0001);
+
+    // The UI's own air byte no longer overrides the tracked one.
+    engine.update_state(&update);
+    engine.touch_move(5, 350.0);
+    assert_eq!(wait_for(&server, |v| v.air == 0b00_0101).air, 0b00_0101);
+    engine.touch_up(4);
+    engine.touch_up(5);
+    assert_eq!(wait_for(&server, |v| v.air == 0).air, 0);
+
+    engine.set_air_geometry(None);
+    engine.update_state(&update);
+    assert_eq!(wait_for(&server, |v| v.air == 0b11_1111).air, 0b11_1111);
+}
+
+#[test]
+fn recognised_flick_drives_the_pulse() {
+    let server = LoopbackServer::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let engine = Engine::new();
+    engine.start(1000);
+    engine.configure(server.local_addr(), 0).unwrap();
+    assert_eq!(sync_on(&engine), ConnState::Connected);
+    engine.set_flick_detector(Some(FlickConfig::default()));
+    engine.set_mickey(1);
+    engine.update_state(&InputUpdate { packet_type: 32, slider_mask: 1, air_mode: 2, ..Default::default() });
+    assert_eq!(wait_for(&server, |v| v.air == 0b10_0000).air, 0b10_0000);
+
+    engine.touch_down(1, 900.0);
+    engine.touch_move(1, 800.0);
+    let view = wait_for(&server, |v| v.air & 0b1_1111 != 0);
+    assert_ne!(view.air & 0b1_1111, 0, "the sweep went out");
+    engine.touch_up(1);
+}
+
+#[test]
+fn delta_mode_sends_changes_and_keepalives_only() {
+    let server = LoopbackServer::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let engine = Engine::new();
+    engine.set_send_mode(SendMode::Delta { repeats: 2, keepalive_ns: 50_000_000 });
+    engine.start(1000);
+    engine.configure(server.local_addr(), 0).unwrap();
+    assert_eq!(sync_on(&engine), ConnState::Connected);
+
+    let update = InputUpdate { packet_type: 32, slider_mask: 0b11, air_mode: 1, ..Default::default() };
+    engine.update_state(&update);
+    wait_for(&server, |v| v.slider == 0b11);
+
+    // Unchanged input for 300 ms: a few keepalives, not 300 packets.
+    let before = server.snapshot().packets;
+    thread::sleep(Duration::from_millis(300));
+    let idle = server.snapshot().packets - before;
+    assert!((3..=12).contains(&idle), "{idle} packets while idle");
+
+    engine.update_state(&InputUpdate { slider_mask: 0b111, ..update });
+    let view = wait_for(&server, |v| v.slider == 0b111);
+    assert_eq!(view.slider, 0b111);
+}
+
+#[test]
+fn led_frames_reach_the_engine() {
+    for protocol_type in [0, 1] {
+        let server = LoopbackServer::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let engine = Engine::new();
+        engine.start(1000);
+        engine.configure(server.local_addr(), protocol_type).unwrap();
+        assert_eq!(sync_on(&engine), ConnState::Connected);
+        assert_eq!(engine.led_version(), 0);
+
+        for step in 1..=2u8 {
+            let mut frame = LedFrame::default();
+            frame.slider[15] = [step, 0x80, 0x40];
+            frame.air[0] = [0x10, 0x20, step];
+            server.send_leds(frame);
+
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while engine.leds().1 != frame && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(5));
+            }
+            assert_eq!(engine.leds(), (u64::from(step), frame), "protocol {protocol_type}");
+        }
+
+        engine.shutdown();
+        assert_eq!(engine.leds(), (0, LedFrame::default()));
+    }
+}
+
+#[test]
+fn ping_measures_rtt_while_idle_and_connected() {
+    for protocol_type in [0, 1] {
+        let server = LoopbackServer::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let engine = Engine::new();
+        engine.set_ping_interval(Some(Duration::from_millis(10)));
+        engine.start(1000);
+        engine.configure(server.local_addr(), protocol_type).unwrap();
+
+        let wait_pongs = |n: u64| {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while engine.rtt().pongs_received < n && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(5));
+            }
+            engine.rtt()
+        };
+
+        // Before any session: the engine is idle and parked between pings.
+        let rtt = wait_pongs(5);
+        assert!(rtt.pongs_received >= 5, "protocol {protocol_type}: {rtt:?}");
+        assert_eq!(engine.state(), ConnState::Idle);
+
+        assert_eq!(sync_on(&engine), ConnState::Connected);
+        let rtt = wait_pongs(rtt.pongs_received + 5);
+        assert!(rtt.smoothed_rtt_ns > 0);
+        assert!(rtt.min_rtt_ns <= rtt.smoothed_rtt_ns);
+        assert!(rtt.smoothed_rtt_ns < 50_000_000, "{rtt:?}");
+        assert!(rtt.pings_sent >= rtt.pongs_received);
+        assert_eq!(rtt.pings_lost, 0);
+        assert_eq!(rtt.loss_ppm, 0);
+
+        engine.shutdown();
+        assert_eq!(engine.rtt(), Default::default());
+    }
+}
+
+#[test]
+fn handshake_agrees_on_common_features() {
+    for protocol_type in [0, 1] {
+        let server = LoopbackServer::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        server.set_features(Some(Features::LED | Features::PING));
+        let engine = Engine::new();
+        engine.set_features(Features::PING | Features::STAMP);
+        engine.start(1000);
+        engine.configure(server.local_addr(), protocol_type).unwrap();
+
+        assert_eq!(negotiated(&engine), Some((PROTOCOL_VERSION, Features::PING)));
+        let hello = server.snapshot().hello;
+        assert_eq!(hello, Some((PROTOCOL_VERSION, Features::PING | Features::STAMP)));
+
+        assert_eq!(sync_on(&engine), ConnState::Connected);
+        wait_for(&server, |v| v.packets > 50);
+        assert_eq!(server.snapshot().stamped, 0);
+    }
+}
+
+#[test]
+fn legacy_server_gets_the_plain_protocol() {
+    let server = LoopbackServer::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    server.set_features(None);
+    let engine = Engine::new();
+    engine.set_ping_interval(Some(Duration::from_millis(10)));
+    engine.start(1000);
+    engine.configure(server.local_addr(), 0).unwrap();
+    assert_eq!(sync_on(&engine), ConnState::Connected);
+
+    engine.update_state(&InputUpdate {
+        packet_type: 16,
+        button_mask: 0b1,
+        slider_mask: 0b11,
+        air_mode: 1,
+        ..Default::default()
+    });
+    let view = wait_for(&server, |v| v.buttons == 0b1);
+    // Hellos went unanswered: no stamps, no full state, no pings.
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(engine.negotiated(), None);
+    assert_eq!(view.slider, 0);
+    assert_eq!(server.snapshot().stamped, 0);
+    assert_eq!(engine.rtt().pings_sent, 0);
+}
+
+#[test]
+fn hellos_never_reach_a_legacy_server_as_syncs() {
+    let server = LoopbackServer::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    server.set_features(None);
+    let engine = Engine::new();
+    engine.start(1000);
+    engine.configure(server.local_addr(), 0).unwrap();
+
+    // Every hello retry goes out and is ignored; the server stays off.
+    wait_for(&server, |v| v.hello.is_some());
+    thread::sleep(Duration::from_millis(1200));
+    let view = server.snapshot();
+    assert_eq!(engine.negotiated(), None);
+    assert_eq!(view.sync_frames, 0);
+    assert!(!view.connected);
+
+    assert_eq!(sync_on(&engine), ConnState::Connected);
+    assert!(server.snapshot().sync_frames >= 1);
+}
+
+#[test]
+fn engine_restarts_after_shutdown() {
+    let server = LoopbackServer::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let engine = Engine::new();
+
+    for _ in 0..2 {
+        engine.start(1000);
+        engine.configure(server.local_addr(), 1).unwrap();
+        assert_eq!(sync_on(&engine), ConnState::Connected);
+
+        let started = Instant::now();
+        engine.shutdown();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(engine.state(), ConnState::Idle);
+
+        // Nothing is sent once the threads are gone (after draining frames
+        // that were already in flight).
+        thread::sleep(Duration::from_millis(50));
+        let before = server.snapshot().packets;
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(server.snapshot().packets, before);
+    }
+}
+
+#[test]
+fn udp_session_over_ipv6() {
+    let Ok(server) = LoopbackServer::bind("[::1]:0".parse().unwrap()) else {
+        return; // no IPv6 on this host
+    };
+    let engine = Engine::new();
+    engine.start(1000);
+    engine.configure_host("::1", server.local_addr().port() as i32, 0).unwrap();
+    assert_eq!(sync_on(&engine), ConnState::Connected);
+    assert!(wait_for(&server, |v| v.connected).connected);
+}
+
+#[test]
+fn paired_session_drops_frames_with_the_wrong_key() {
+    let server = LoopbackServer::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    server.set_pairing_key(Some(b"shared secret"));
+    let engine = Engine::new();
+    engine.set_pairing_key(Some(b"shared secret"));
+    engine.start(1000);
+    engine.configure(server.local_addr(), 0).unwrap();
+    assert_eq!(sync_on(&engine), ConnState::Connected);
+    assert!(negotiated(&engine).is_some());
+
+    engine.update_state(&InputUpdate {
+        packet_type: 16,
+        button_mask: 0b10,
+        air_mode: 1,
+        ..Default::default()
+    });
+    let view = wait_for(&server, |v| v.buttons == 0b10);
+    assert_eq!(view.buttons, 0b10);
+    assert_eq!(view.rejected, 0);
+    assert_eq!(view.bad_frames, 0);
+    assert_eq!(engine.auth_stats().unauthenticated, 0);
+
+    // A stranger's hellos are dropped and counted; with no hello ack it
+    // sends nothing else, so its sync never confirms.
+    let stranger = Engine::new();
+    stranger.set_pairing_key(Some(b"guessed"));
+    stranger.start(1000);
+    stranger.configure(server.local_addr(), 0).unwrap();
+    stranger.toggle_sync().unwrap();
+    assert!(wait_for(&server, |v| v.rejected >= 2).rejected >= 2);
+    assert_eq!(stranger.state(), ConnState::Syncing { target_connected: true });
+    assert_eq!(stranger.negotiated(), None);
+
+    // Unpaired frames count the same way.
+    stranger.set_pairing_key(None);
+    if stranger.state() == ConnState::Idle {
+        stranger.toggle_sync().unwrap();
+    }
+    let rejected = server.snapshot().rejected;
+    assert!(wait_for(&server, |v| v.rejected > rejected + 10).rejected > rejected + 10);
+    assert_eq!(engine.state(), ConnState::Connected);
+}
+
+#[test]
+fn setting_the_same_key_again_keeps_rejecting_replays() {
+    let key = b"shared secret";
+    let server = LoopbackServer::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    server.set_pairing_key(Some(key));
+    // Only a hello may be sealed before the server's nonce is known.
+    let hello = ClientPacket::Hello { version: PROTOCOL_VERSION, features: Features::ALL, nonce: 7 };
+    let sealed = seal(key, Direction::ToServer, 0, 1, hello.encode(Transport::Udp).as_bytes());
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client.send_to(sealed.as_bytes(), server.local_addr()).unwrap();
+    let view = wait_for(&server, |v| v.hello.is_some());
+    assert_eq!((view.hello, view.rejected), (Some((PROTOCOL_VERSION, Features::ALL)), 0));
+
+    server.set_pairing_key(Some(key));
+    client.send_to(sealed.as_bytes(), server.local_addr()).unwrap();
+    assert_eq!(wait_for(&server, |v| v.rejected == 1).rejected, 1);
+
+    let plain = ClientPacket::Buttons(0b100).encode(Transport::Udp);
+    let buttons = seal(key, Direction::ToServer, 0, 2, plain.as_bytes());
+    client.send_to(buttons.as_bytes(), server.local_addr()).unwrap();
+    let view = wait_for(&server, |v| v.rejected == 2);
+    assert_eq!((view.rejected, view.buttons), (2, 0));
+}
+
+#[test]
+fn recorded_server_frames_do_not_open_in_a_new_session() {
+    const KEY: &[u8] = b"shared secret";
+    const SERVER_NONCE: u64 = 0x5EED;
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    server.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    // Answers the engine's hello and returns the sealed ack and its address.
+    let answer_hello = |counter: u64| {
+        let mut buf = [0u8; 64];
+        loop {
+            let (len, from) = server.recv_from(&mut buf).unwrap();
+            let Ok((plain, _)) = open(KEY, Direction::ToServer, 0, &buf[..len]) else { continue };
+            if let Ok((ClientPacket::Hello { nonce, .. }, _)) = ClientPacket::decode(plain.as_bytes()) {
+                let ack = ServerPacket::HelloAck { version: PROTOCOL_VERSION, features: Features::NONE, nonce: SERVER_NONCE };
+                let sealed = seal(KEY, Direction::ToClient, nonce, counter, ack.encode(Transport::Udp).as_bytes());
+                server.send_to(sealed.as_bytes(), from).unwrap();
+                return (sealed, nonce, from);
+            }
+        }
+    };
+
+    let first = Engine::new();
+    first.set_pairing_key(Some(KEY));
+    first.start(1000);
+    first.configure(server.local_addr().unwrap(), 0).unwrap();
+    let (ack, nonce, from) = answer_hello(1);
+    assert!(negotiated(&first).is_some());
+    first.toggle_sync().unwrap();
+    let confirm = ServerPacket::SyncConfirm { connected: true }.encode(Transport::Udp);
+    let confirm = seal(KEY, Direction::ToClient, nonce, 2, confirm.as_bytes());
+    server.send_to(confirm.as_bytes(), from).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while first.state() != ConnState::Connected && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(first.state(), ConnState::Connected);
+    first.shutdown();
+
+    // Same key, new session: the recorded ack and confirm are refused.
+    let second = Engine::new();
+    second.set_pairing_key(Some(KEY));
+    second.start(1000);
+    second.configure(server.local_addr().unwrap(), 0).unwrap();
+    let (_, second_nonce, from) = answer_hello(1);
+    assert_ne!(second_nonce, nonce);
+    assert!(negotiated(&second).is_some());
+    assert_eq!(second.toggle_sync(), Ok(ConnState::Syncing { target_connected: true }));
+    server.send_to(ack.as_bytes(), from).unwrap();
+    server.send_to(confirm.as_bytes(), from).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while second.auth_stats().unauthenticated < 2 && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(second.auth_stats().unauthenticated, 2);
+    assert_eq!(second.state(), ConnState::Syncing { target_connected: true });
+}
+
+#[test]
+fn card_and_sync_survive_lost_messages_and_acks() {
+    let server = LoopbackServer::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let engine = Engine::new();
+    engine.start(1000);
+    engine.configure(server.local_addr(), 0).unwrap();
+    let (_, agreed) = negotiated(&engine).unwrap();
+    assert!(agreed.contains(Features::RELIABLE));
+
+    // The third copy of the sync gets through, but its confirm is lost: the
+    // retransmission after the ack brings another one before the timeout.
+    server.lose_messages(2);
+    server.lose_confirms(1);
+    let requested = Instant::now();
+    assert_eq!(engine.toggle_sync(), Ok(ConnState::Syncing { target_connected: true }));
+    while engine.state() != ConnState::Connected && requested.elapsed() < Duration::from_millis(450) {
+        assert_ne!(engine.state(), ConnState::Idle, "sync timed out");
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(engine.state(), ConnState::Connected);
+    let view = wait_for(&server, |v| v.messages == 1);
+    assert_eq!((view.messages, view.duplicates), (1, 1));
+    let duplicates = view.duplicates;
+
+    // The swipe is applied once even though its ack is lost twice.
+    server.lose_acks(2);
+    let card = [0x34; 10];
+    engine.update_state(&InputUpdate {
+        packet_type: 48,
+        air_mode: 1,
+        card_bcd: Some(card),
+        ..Default::default()
+    });
+    let view = wait_for(&server, |v| v.duplicates == duplicates + 2);
+    assert_eq!(view.card, Some(card));
+    assert_eq!(view.messages, 2);
+    assert_eq!(view.duplicates, duplicates + 2);
+
+    // Acked: the held card keeps going out every tick, but not as a message.
+    let packets = server.snapshot().packets;
+    thread::sleep(Duration::from_millis(100));
+    let view = server.snapshot();
+    assert_eq!((view.messages, view.duplicates), (2, duplicates + 2));
+    assert!(view.packets > packets + 50);
+    engine.update_state(&InputUpdate {
+        packet_type: 32,
+        slider_mask: 0b1,
+        air_mode: 1,
+        ..Default::default()
+    });
+    assert_eq!(wait_for(&server, |v| v.slider == 0b1).messages, 2);
+}
+
+fn run_brokenithm_session(protocol_type: u32, transport: Transport) {
+    let server = LoopbackServer::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    server.set_dialect(Dialect::Brokenithm);
+    let engine = Engine::new();
+    engine.set_dialect(Dialect::Brokenithm);
+    engine.set_ping_interval(Some(Duration::from_millis(10)));
+    engine.start(1000);
+    engine.configure(server.local_addr(), protocol_type).unwrap();
+
+    // Confirmed locally; no hello was ever sent.
+    assert_eq!(sync_on(&engine), ConnState::Connected);
+    engine.update_state(&InputUpdate {
+        packet_type: 16,
+        button_mask: 0b101,
+        air_byte: 0b11,
+        slider_mask: 0x8000_0001,
+        air_mode: 1,
+        ..Default::default()
+    });
+    let view = wait_for(&server, |v| v.slider == 0x8000_0001 && v.coins == 1);
+    assert!(view.connected);
+    assert_eq!((view.buttons, view.air, view.coins), (0b100, 0b11, 1));
+    assert_eq!(view.last_transport, Some(transport));
+    assert_eq!(view.bad_frames, 0);
+    assert_eq!(view.hello, None);
+
+    let card = [0x56; 10];
+    engine.update_state(&InputUpdate { packet_type: 48, air_mode: 1, card_bcd: Some(card), ..Default::default() });
+    assert_eq!(wait_for(&server, |v| v.card.is_some()).card, Some(card));
+    engine.update_state(&InputUpdate { packet_type: 32, air_mode: 1, ..Default::default() });
+    assert_eq!(wait_for(&server, |v| v.card.is_none()).card, None);
+
+    let mut frame = LedFrame::default();
+    frame.slider[3] = [1, 2, 3];
+    server.send_leds(frame);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while engine.led_version() == 0 && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(engine.leds().1, frame);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while engine.rtt().pongs_received == 0 && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert!(engine.rtt().pongs_received > 0);
+    assert_eq!(engine.negotiated(), None);
+}
+
+#[test]
+fn udp_session_against_brokenithm_server() {
+    run_brokenithm_session(0, Transport::Udp);
+}
+
+#[test]
+fn tcp_session_against_brokenithm_server() {
+    run_brokenithm_session(1, Transport::Tcp);
+}
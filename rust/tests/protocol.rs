@@ -0,0 +1,210 @@
+use rustnithm::protocol::{
+    split_tcp_frame, write_tcp_frame, ClientPacket, LedFrame, ProtocolError, ServerPacket, Stamp,
+    Features, Transport, LED_FRAME_LEN,
+};
+
+const CARD: [u8; 10] = [0x01, 0x23, 0x45, 0x67, 0x89, 0x01, 0x23, 0x45, 0x67, 0x89];
+
+/// (packet, UDP bytes, TCP bytes without the length prefix)
+fn client_golden() -> Vec<(ClientPacket, Vec<u8>, Vec<u8>)> {
+    vec![
+        (ClientPacket::Sync { target_connected: true }, vec![0x00, 0x30], vec![0x80, 0x30]),
+        (ClientPacket::Sync { target_connected: false }, vec![0x00, 0x80], vec![0x80, 0x80]),
+        (ClientPacket::Buttons(0x05), vec![0x10, 0x05], vec![0x90, 0x05]),
+        (
+            ClientPacket::AirSlider { air: 0x21, slider: 0x8000_0001 },
+            vec![0x20, 0x21, 0x01, 0x00, 0x00, 0x80],
+            vec![0xA0, 0x21, 0x01, 0x00, 0x00, 0x80],
+        ),
+        (
+            ClientPacket::FullState { buttons: 0x05, air: 0x21, slider: 0x8000_0001 },
+            vec![0x24, 0x05, 0x21, 0x01, 0x00, 0x00, 0x80],
+            vec![0xA4, 0x05, 0x21, 0x01, 0x00, 0x00, 0x80],
+        ),
+        (
+            ClientPacket::Ping { id: 0x0102, micros: 0x0304_0506 },
+            vec![0x04, 0x02, 0x01, 0x06, 0x05, 0x04, 0x03],
+            vec![0x84, 0x02, 0x01, 0x06, 0x05, 0x04, 0x03],
+        ),
+        (
+            ClientPacket::Hello { version: 1, features: Features(0x000F), nonce: 0x0102_0304_0506_0708 },
+            vec![0x34, 0x01, 0x0F, 0x00, 0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01],
+            vec![0xB4, 0x01, 0x0F, 0x00, 0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01],
+        ),
+        (
+            ClientPacket::Card(CARD),
+            [&[0x30][..], &CARD[..]].concat(),
+            [&[0xB0][..], &CARD[..]].concat(),
+        ),
+    ]
+}
+
+#[test]
+fn client_packets_match_golden_bytes() {
+    for (packet, udp, tcp) in client_golden() {
+        assert_eq!(packet.encode(Transport::Udp).as_bytes(), &udp[..], "{packet:?} udp");
+        assert_eq!(packet.encode(Transport::Tcp).as_bytes(), &tcp[..], "{packet:?} tcp");
+        assert_eq!(ClientPacket::decode(&udp), Ok((packet, Transport::Udp)));
+        assert_eq!(ClientPacket::decode(&tcp), Ok((packet, Transport::Tcp)));
+    }
+}
+
+#[test]
+fn client_packets_round_trip_through_tcp_framing() {
+    for (packet, _, tcp) in client_golden() {
+        let mut stream = Vec::new();
+        write_tcp_frame(packet.encode(Transport::Tcp).as_bytes(), &mut stream);
+        assert_eq!(&stream[..2], &(tcp.len() as u16).to_le_bytes());
+        assert_eq!(&stream[2..], &tcp[..]);
+
+        let (frame, consumed) = split_tcp_frame(&stream).unwrap();
+        assert_eq!(consumed, stream.len());
+        assert_eq!(ClientPacket::decode(frame), Ok((packet, Transport::Tcp)));
+    }
+}
+
+#[test]
+fn stamped_packets_append_trailer() {
+    let stamp = Stamp { seq: 0xBEEF, micros: 0x0102_0304 };
+    let trailer = [0xEF, 0xBE, 0x04, 0x03, 0x02, 0x01];
+    for (packet, udp, tcp) in client_golden() {
+        for (transport, plain) in [(Transport::Udp, udp), (Transport::Tcp, tcp)] {
+            let expected = [&[plain[0] | 0x08][..], &plain[1..], &trailer[..]].concat();
+            let encoded = packet.encode_stamped(transport, Some(stamp));
+            assert_eq!(encoded.as_bytes(), &expected[..], "{packet:?} {transport:?}");
+            assert_eq!(ClientPacket::decode_stamped(&expected), Ok((packet, transport, Some(stamp))));
+            // Plain decoding still understands the packet, minus the stamp.
+            assert_eq!(ClientPacket::decode(&expected), Ok((packet, transport)));
+        }
+        assert_eq!(packet.encode_stamped(Transport::Udp, None).as_bytes(), packet.encode(Transport::Udp).as_bytes());
+    }
+}
+
+#[test]
+fn reliable_messages_carry_their_id_before_the_stamp() {
+    let card = ClientPacket::Card(CARD);
+    let expected = [&[0x32][..], &CARD[..], &[0x34, 0x12][..]].concat();
+    assert_eq!(card.encode_message(Transport::Udp, Some(0x1234), None).as_bytes(), &expected[..]);
+    assert_eq!(ClientPacket::decode_message(&expected), Ok((card, Transport::Udp, Some(0x1234), None)));
+
+    let stamp = Stamp { seq: 0xBEEF, micros: 0x0102_0304 };
+    let sync = ClientPacket::Sync { target_connected: true };
+    let expected = [0x0A, 0x30, 0x01, 0x00, 0xEF, 0xBE, 0x04, 0x03, 0x02, 0x01];
+    assert_eq!(sync.encode_message(Transport::Udp, Some(1), Some(stamp)).as_bytes(), &expected[..]);
+    assert_eq!(ClientPacket::decode_message(&expected), Ok((sync, Transport::Udp, Some(1), Some(stamp))));
+    assert_eq!(ClientPacket::decode(&expected), Ok((sync, Transport::Udp)));
+
+    assert_eq!(
+        ClientPacket::decode(&[0x02, 0x30]),
+        Err(ProtocolError::Length { kind: "message id", expected: 3, actual: 2 })
+    );
+
+    let ack = ServerPacket::Ack { id: 0x1234 };
+    assert_eq!(ack.encode(Transport::Udp).as_bytes(), [0x70, 0x34, 0x12]);
+    assert_eq!(ack.encode(Transport::Tcp).as_bytes(), [0xF0, 0x34, 0x12]);
+    assert_eq!(ServerPacket::decode(&[0x70, 0x34, 0x12]), Ok(ack));
+}
+
+#[test]
+fn server_packets_match_golden_bytes() {
+    let golden = [
+        (ServerPacket::SyncConfirm { connected: true }, [0x40, 0x10], [0xC0, 0x10]),
+        (ServerPacket::SyncConfirm { connected: false }, [0x40, 0x00], [0xC0, 0x00]),
+    ];
+    for (packet, udp, tcp) in golden {
+        assert_eq!(packet.encode(Transport::Udp).as_bytes(), udp);
+        assert_eq!(packet.encode(Transport::Tcp).as_bytes(), tcp);
+        assert_eq!(ServerPacket::decode(&udp), Ok(packet));
+        assert_eq!(ServerPacket::decode(&tcp), Ok(packet));
+    }
+
+    let ack = ServerPacket::HelloAck { version: 1, features: Features::LED | Features::PING, nonce: 0x2A };
+    let udp = [0x74, 0x01, 0x03, 0x00, 0x2A, 0, 0, 0, 0, 0, 0, 0];
+    assert_eq!(ack.encode(Transport::Udp).as_bytes(), udp);
+    assert_eq!(ack.encode(Transport::Tcp).as_bytes()[0], 0xF4);
+    assert_eq!(ServerPacket::decode(&udp), Ok(ack));
+    // A sync confirm whose reserved bits happen to spell a hello stays one.
+    assert_eq!(ServerPacket::decode(&[0x44, 0x48]), Ok(ServerPacket::SyncConfirm { connected: false }));
+    assert_eq!(ServerPacket::decode(&[0x40, 0x58]), Ok(ServerPacket::SyncConfirm { connected: true }));
+
+    let pong = ServerPacket::Pong { id: 0x0102, micros: 0x0304_0506 };
+    let udp = [0x60, 0x02, 0x01, 0x06, 0x05, 0x04, 0x03];
+    assert_eq!(pong.encode(Transport::Udp).as_bytes(), udp);
+    assert_eq!(pong.encode(Transport::Tcp).as_bytes()[0], 0xE0);
+    assert_eq!(ServerPacket::decode(&udp), Ok(pong));
+}
+
+#[test]
+fn led_frames_match_golden_bytes() {
+    let mut frame = LedFrame::default();
+    frame.slider[0] = [0xFF, 0x00, 0x00];
+    frame.slider[30] = [0x00, 0xFF, 0x00];
+    frame.air[5] = [0x01, 0x02, 0x03];
+
+    let mut payload = [0u8; LED_FRAME_LEN];
+    payload[0] = 0xFF;
+    payload[30 * 3 + 1] = 0xFF;
+    payload[LED_FRAME_LEN - 3..].copy_from_slice(&[0x01, 0x02, 0x03]);
+    assert_eq!(frame.to_bytes(), payload);
+    assert_eq!(LedFrame::from_bytes(&payload), frame);
+
+    let udp = [&[0x50][..], &payload[..]].concat();
+    let tcp = [&[0xD0][..], &payload[..]].concat();
+    let packet = ServerPacket::Led(frame);
+    assert_eq!(packet.encode(Transport::Udp).as_bytes(), &udp[..]);
+    assert_eq!(packet.encode(Transport::Tcp).as_bytes(), &tcp[..]);
+    assert_eq!(ServerPacket::decode(&udp), Ok(packet));
+    assert_eq!(ServerPacket::decode(&tcp), Ok(packet));
+}
+
+#[test]
+fn split_tcp_frame_waits_for_complete_frames() {
+    assert_eq!(split_tcp_frame(&[]), None);
+    assert_eq!(split_tcp_frame(&[0x02]), None);
+    assert_eq!(split_tcp_frame(&[0x02, 0x00, 0x40]), None);
+    assert_eq!(split_tcp_frame(&[0x02, 0x00, 0x40, 0x10, 0xFF]), Some((&[0x40, 0x10][..], 4)));
+}
+
+#[test]
+fn malformed_frames_are_rejected() {
+    assert_eq!(ClientPacket::decode(&[]), Err(ProtocolError::Empty));
+    assert_eq!(
+        ClientPacket::decode(&[0x20, 0x00]),
+        Err(ProtocolError::Length { kind: "air+slider", expected: 6, actual: 2 })
+    );
+    assert_eq!(ClientPacket::decode(&[0x00, 0x01]), Err(ProtocolError::InvalidSyncPayload(0x01)));
+    assert_eq!(ClientPacket::decode(&[0x40, 0x10]), Err(ProtocolError::UnknownHeader(0x40)));
+    // Buttons is the one type without an extended variant.
+    assert_eq!(ClientPacket::decode(&[0x14, 0x05]), Err(ProtocolError::UnknownHeader(0x14)));
+    assert_eq!(
+        ClientPacket::decode(&[0x24, 0x05, 0x21, 0x01, 0x00, 0x00]),
+        Err(ProtocolError::Length { kind: "full state", expected: 7, actual: 6 })
+    );
+    assert_eq!(
+        ClientPacket::decode(&[0x18, 0x05, 0x01]),
+        Err(ProtocolError::Length { kind: "stamp", expected: 7, actual: 3 })
+    );
+    assert_eq!(
+        ClientPacket::decode(&[0x18, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+        Err(ProtocolError::Length { kind: "buttons", expected: 2, actual: 3 })
+    );
+
+    assert_eq!(ServerPacket::decode(&[]), Err(ProtocolError::Empty));
+    assert_eq!(ServerPacket::decode(&[0x10, 0x05]), Err(ProtocolError::UnknownHeader(0x10)));
+    assert_eq!(
+        ServerPacket::decode(&[0x70, 0x00]),
+        Err(ProtocolError::Length { kind: "ack", expected: 3, actual: 2 })
+    );
+    assert_eq!(
+        ServerPacket::decode(&[0x60, 0x00]),
+        Err(ProtocolError::Length { kind: "pong", expected: 7, actual: 2 })
+    );
+    assert_eq!(
+        ServerPacket::decode(&[0x50, 0x00]),
+        Err(ProtocolError::Length { kind: "led", expected: 1 + LED_FRAME_LEN, actual: 2 })
+    );
+    assert_eq!(
+        ServerPacket::decode(&[0x40]),
+        Err(ProtocolError::Length { kind: "sync confirm", expected: 2, actual: 1 })
+    );
+}
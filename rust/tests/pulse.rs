@@ -0,0 +1,202 @@
+use rustnithm::pulse::{PulseProfile, PulseState, RetriggerPolicy, Sweep, ZoneOrder, MAX_WAITING};
+use rustnithm::{ConfigError, Engine};
+
+const MS: u64 = 1_000_000;
+
+/// The air byte at every `step` from 0 until the pulse has ended.
+fn bytes(pulse: &mut PulseState, step: u64) -> Vec<u8> {
+    pulse.trigger(0);
+    let mut out = Vec::new();
+    let mut now = 0;
+    while pulse.is_active() {
+        out.push(pulse.get_air_byte(now, false));
+        now += step;
+    }
+    out
+}
+
+#[test]
+fn default_profile_sweeps_zones_one_to_five() {
+    let mut pulse = PulseState::new();
+    assert_eq!(pulse.get_air_byte(0, false), 0b10_0000, "baseline only while idle");
+    assert_eq!(
+        bytes(&mut pulse, 10 * MS),
+        [0b10_0001, 0b10_0010, 0b10_0100, 0b10_1000, 0b11_0000, 0b10_0000]
+    );
+    // Sampled finer, each step lasts 10 ms.
+    assert_eq!(bytes(&mut pulse, 5 * MS)[..4], [0b10_0001, 0b10_0001, 0b10_0010, 0b10_0010]);
+}
+
+#[test]
+fn order_baseline_and_sweeps_shape_the_pulse() {
+    let profile = |order, baseline, sweeps| {
+        PulseState::with_profile(PulseProfile { step_ns: 4 * MS, order, baseline, sweeps })
+    };
+
+    let mut down = profile(ZoneOrder::Down, 0b10_0000, 1);
+    assert_eq!(bytes(&mut down, 4 * MS), [0b11_0000, 0b10_1000, 0b10_0100, 0b10_0010, 0b10_0001, 0b10_0000]);
+
+    let mut both = profile(ZoneOrder::Both, 0b11_1001, 1);
+    assert_eq!(bytes(&mut both, 4 * MS), [0b11_1011, 0b11_1101, 0b11_1011, 0b11_1001]);
+
+    let mut twice = profile(ZoneOrder::Up, 0b11_1100, 2);
+    assert_eq!(bytes(&mut twice, 4 * MS), [0b11_1101, 0b11_1110, 0b11_1101, 0b11_1110, 0b11_1100]);
+
+    let mut no_baseline = profile(ZoneOrder::Up, 0, 1);
+    assert_eq!(bytes(&mut no_baseline, 4 * MS), [0b1, 0b10, 0b100, 0b1000, 0b1_0000, 0b10_0000, 0]);
+}
+
+/// The air byte every 10 ms from 0 to `until_ms`, after `setup` at time 0.
+fn timeline(pulse: &mut PulseState, until_ms: u64, mut at: impl FnMut(&mut PulseState, u64)) -> Vec<u8> {
+    (0..=until_ms / 10)
+        .map(|i| {
+            at(pulse, i * 10);
+            pulse.get_air_byte(i * 10 * MS, false)
+        })
+        .collect()
+}
+
+#[test]
+fn air_down_sweeps_from_the_top() {
+    let mut pulse = PulseState::new();
+    pulse.trigger_air_down(0);
+    assert_eq!(pulse.running(), Some(Sweep::AirDown));
+    let down = timeline(&mut pulse, 50, |_, _| {});
+    assert_eq!(down, [0b11_0000, 0b10_1000, 0b10_0100, 0b10_0010, 0b10_0001, 0b10_0000]);
+    assert!(!pulse.is_active());
+
+    // Whatever order flicks use, and as many sweeps.
+    let profile = PulseProfile { order: ZoneOrder::Both, baseline: 0b11_1100, sweeps: 2, ..PulseProfile::default() };
+    let mut pulse = PulseState::with_profile(profile);
+    pulse.trigger_air_down(0);
+    assert_eq!(timeline(&mut pulse, 40, |_, _| {}), [0b11_1110, 0b11_1101, 0b11_1110, 0b11_1101, 0b11_1100]);
+}
+
+#[test]
+fn sweeps_of_the_other_kind_wait_their_turn() {
+    // An air-down 20 ms into a flick's sweep starts when that one ends.
+    let mut pulse = PulseState::new();
+    let bytes = timeline(&mut pulse, 100, |p, ms| match ms {
+        0 => p.trigger(0),
+        20 => p.trigger_air_down(20 * MS),
+        _ => {}
+    });
+    assert_eq!(
+        bytes,
+        [
+            0b10_0001, 0b10_0010, 0b10_0100, 0b10_1000, 0b11_0000,
+            0b11_0000, 0b10_1000, 0b10_0100, 0b10_0010, 0b10_0001,
+            0b10_0000,
+        ]
+    );
+
+    // And the other way round; a repeated flick meanwhile keeps only one.
+    let mut pulse = PulseState::new();
+    let bytes = timeline(&mut pulse, 70, |p, ms| match ms {
+        0 => p.trigger_air_down(0),
+        10 | 30 => p.trigger(ms * MS),
+        _ => {}
+    });
+    assert_eq!(bytes[4..], [0b10_0001, 0b10_0001, 0b10_0010, 0b10_0100]);
+
+    // A trigger of the running kind still restarts it.
+    let mut pulse = PulseState::new();
+    let bytes = timeline(&mut pulse, 30, |p, ms| if ms == 0 || ms == 20 { p.trigger_air_down(ms * MS) });
+    assert_eq!(bytes, [0b11_0000, 0b10_1000, 0b11_0000, 0b10_1000]);
+
+    pulse.trigger(30 * MS);
+    pulse.stop();
+    assert_eq!(pulse.get_air_byte(100 * MS, false), 0b10_0000, "stop drops the waiting sweep too");
+}
+
+/// Three flicks 20 ms apart under `policy`, sampled every 10 ms.
+fn three_flicks(policy: RetriggerPolicy) -> Vec<u8> {
+    let mut pulse = PulseState::new();
+    pulse.set_retrigger(policy);
+    assert_eq!(pulse.retrigger(), policy);
+    timeline(&mut pulse, 160, |p, ms| if ms <= 40 && ms % 20 == 0 { p.trigger(ms * MS) })
+}
+
+#[test]
+fn retrigger_policy_decides_what_a_flick_does_mid_sweep() {
+    const SWEEP: [u8; 5] = [0b10_0001, 0b10_0010, 0b10_0100, 0b10_1000, 0b11_0000];
+    const IDLE: u8 = 0b10_0000;
+
+    // Restart cuts the first two sweeps short.
+    let restart = three_flicks(RetriggerPolicy::Restart);
+    assert_eq!(restart[..9], [SWEEP[0], SWEEP[1], SWEEP[0], SWEEP[1], SWEEP[0], SWEEP[1], SWEEP[2], SWEEP[3], SWEEP[4]]);
+    assert!(restart[9..].iter().all(|&b| b == IDLE));
+
+    // Queue plays three whole sweeps back to back.
+    let queue = three_flicks(RetriggerPolicy::Queue);
+    assert_eq!(queue[..15], [SWEEP, SWEEP, SWEEP].concat()[..]);
+    assert_eq!(queue[15], IDLE);
+
+    // Ignore plays only the first.
+    let ignore = three_flicks(RetriggerPolicy::Ignore);
+    assert_eq!(ignore[..5], SWEEP);
+    assert!(ignore[5..].iter().all(|&b| b == IDLE));
+}
+
+#[test]
+fn queued_sweeps_keep_their_order_and_are_bounded() {
+    let mut pulse = PulseState::new();
+    pulse.set_retrigger(RetriggerPolicy::Queue);
+    pulse.trigger(0);
+    pulse.trigger_air_down(0);
+    pulse.trigger(0);
+    pulse.trigger_air_down(0);
+    assert_eq!(pulse.waiting(), 3);
+    let mut kinds = Vec::new();
+    for step in 0..20 {
+        pulse.get_air_byte(step * 10 * MS, false);
+        if step % 5 == 0 {
+            kinds.push(pulse.running());
+        }
+    }
+    assert_eq!(kinds, [Some(Sweep::Flick), Some(Sweep::AirDown), Some(Sweep::Flick), Some(Sweep::AirDown)]);
+
+    for _ in 0..2 * MAX_WAITING {
+        pulse.trigger(0);
+    }
+    assert_eq!(pulse.waiting(), MAX_WAITING);
+
+    // A new profile or policy does not lose the other.
+    pulse.set_profile(PulseProfile { sweeps: 2, ..PulseProfile::default() });
+    assert_eq!((pulse.waiting(), pulse.is_active()), (0, false));
+    assert_eq!(pulse.retrigger(), RetriggerPolicy::Queue);
+}
+
+#[test]
+fn auto_mode_starts_over() {
+    let mut pulse = PulseState::with_profile(PulseProfile { sweeps: 1, ..PulseProfile::default() });
+    pulse.trigger(0);
+    assert_eq!(pulse.get_air_byte(49 * MS, true), 0b11_0000);
+    assert_eq!(pulse.get_air_byte(50 * MS, true), 0b10_0000);
+    assert!(pulse.is_active());
+    assert_eq!(pulse.get_air_byte(50 * MS, true), 0b10_0001);
+}
+
+#[test]
+fn invalid_profiles_are_rejected() {
+    let invalid = [
+        PulseProfile { step_ns: 0, ..PulseProfile::default() },
+        PulseProfile { sweeps: 0, ..PulseProfile::default() },
+        PulseProfile { baseline: 0b100_0000, ..PulseProfile::default() },
+        PulseProfile { baseline: 0b11_1111, ..PulseProfile::default() },
+    ];
+    let engine = Engine::new();
+    for profile in invalid {
+        assert!(
+            matches!(engine.set_pulse_profile(profile), Err(ConfigError::InvalidPulseProfile(_))),
+            "{profile:?}"
+        );
+    }
+    assert!(matches!(ZoneOrder::try_from(3), Err(ConfigError::InvalidPulseProfile(_))));
+    assert!(matches!(RetriggerPolicy::try_from(3), Err(ConfigError::InvalidPulseProfile(_))));
+    assert_eq!(engine.pulse_profile(), PulseProfile::default());
+
+    let profile = PulseProfile { order: ZoneOrder::Both, sweeps: 3, ..PulseProfile::default() };
+    engine.set_pulse_profile(profile).unwrap();
+    assert_eq!(engine.pulse_profile(), profile);
+}
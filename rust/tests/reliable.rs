@@ -0,0 +1,96 @@
+use rustnithm::protocol::ClientPacket;
+use rustnithm::reliable::{DedupeWindow, ReliableSender, INITIAL_RTO_NS, MAX_ATTEMPTS};
+
+const MS: u64 = 1_000_000;
+const CARD: ClientPacket = ClientPacket::Card([0x12; 10]);
+const SYNC: ClientPacket = ClientPacket::Sync { target_connected: true };
+
+/// Ticks at 1 kHz from `from` to `to` and returns the ticks that sent.
+fn sends(sender: &mut ReliableSender, packet: ClientPacket, from: u64, to: u64) -> Vec<(u64, u16)> {
+    (from..to).filter_map(|ms| sender.poll(&packet, ms * MS).map(|id| (ms, id))).collect()
+}
+
+#[test]
+fn retransmits_with_backoff_until_acked() {
+    let mut sender = ReliableSender::new(7);
+    let sent = sends(&mut sender, CARD, 0, 100);
+    assert_eq!(sent, vec![(0, 7), (20, 7), (60, 7)]);
+    assert_eq!(sender.retransmits(), 2);
+
+    assert!(!sender.on_ack(6));
+    assert!(sender.on_ack(7));
+    assert_eq!(sender.pending(), None);
+    // The same swipe still held: nothing more to send.
+    assert!(sends(&mut sender, CARD, 100, 1000).is_empty());
+}
+
+#[test]
+fn acked_sync_stays_pending_until_confirmed() {
+    let mut sender = ReliableSender::new(3);
+    assert_eq!(sends(&mut sender, SYNC, 0, 10), vec![(0, 3)]);
+    assert!(sender.on_ack(3));
+    assert_eq!(sender.pending(), Some(SYNC));
+    // The confirm was lost: the retransmission asks for another.
+    assert_eq!(sends(&mut sender, SYNC, 10, 30), vec![(20, 3)]);
+
+    assert!(!sender.on_sync_confirm(false));
+    assert!(sender.on_sync_confirm(true));
+    assert_eq!(sender.pending(), None);
+    assert!(sends(&mut sender, SYNC, 30, 1000).is_empty());
+}
+
+#[test]
+fn gives_up_after_max_attempts() {
+    let mut sender = ReliableSender::new(0);
+    let sent = sends(&mut sender, SYNC, 0, 2000);
+    assert_eq!(sent.len() as u32, MAX_ATTEMPTS);
+    assert_eq!(sent.iter().map(|&(ms, _)| ms).collect::<Vec<_>>(), vec![0, 20, 60, 140, 300]);
+    assert_eq!(sender.given_up(), 1);
+    assert_eq!(sender.pending(), None);
+}
+
+#[test]
+fn a_new_intent_gets_a_new_id() {
+    let mut sender = ReliableSender::new(u16::MAX);
+    assert_eq!(sender.poll(&CARD, 0), Some(u16::MAX));
+    assert!(sender.on_ack(u16::MAX));
+
+    // Released and swiped again: a fresh message, even with the same card.
+    sender.idle();
+    assert_eq!(sender.poll(&CARD, MS), Some(0));
+
+    // A different message replaces the pending one.
+    assert_eq!(sender.poll(&SYNC, 2 * MS), Some(1));
+    assert_eq!(sender.pending(), Some(SYNC));
+    assert!(!sender.on_ack(0));
+
+    sender.reset(100);
+    assert_eq!(sender.pending(), None);
+    assert_eq!(sender.poll(&SYNC, 3 * MS), Some(100));
+    assert_eq!(sender.poll(&SYNC, 3 * MS + INITIAL_RTO_NS), Some(100));
+}
+
+#[test]
+fn control_messages_are_sync_and_card_only() {
+    assert!(ReliableSender::is_control(&SYNC));
+    assert!(ReliableSender::is_control(&CARD));
+    assert!(!ReliableSender::is_control(&ClientPacket::Buttons(1)));
+    assert!(!ReliableSender::is_control(&ClientPacket::AirSlider { air: 0, slider: 0 }));
+}
+
+#[test]
+fn dedupe_window_remembers_recent_ids() {
+    let mut window = DedupeWindow::default();
+    assert!(window.accept(5));
+    assert!(!window.accept(5));
+    for id in 100..131 {
+        assert!(window.accept(id));
+    }
+    assert!(!window.accept(5));
+    // One more pushes the oldest out.
+    assert!(window.accept(131));
+    assert!(window.accept(5));
+
+    window.clear();
+    assert!(window.accept(131));
+}
@@ -0,0 +1,83 @@
+use rustnithm::scheduler::{MissPolicy, TickScheduler};
+
+const INTERVAL: u64 = 1_000_000; // 1000 Hz
+const TICKS: u64 = 1_000_000;
+
+/// Deterministic xorshift, so the simulated wakeup jitter is reproducible.
+struct Jitter(u64);
+
+impl Jitter {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Mostly small overshoots, occasionally a stall of a few intervals.
+    fn wakeup_delay(&mut self) -> u64 {
+        let r = self.next();
+        if r.is_multiple_of(1000) {
+            r % (3 * INTERVAL)
+        } else {
+            r % 80_000
+        }
+    }
+}
+
+/// Simulates a loop that sleeps until the next deadline and wakes up late by
+/// a jittered amount. Returns (fired, missed, final clock, scheduler).
+fn simulate(policy: MissPolicy) -> (u64, u64, u64, TickScheduler) {
+    let mut jitter = Jitter(0x9E37_79B9_7F4A_7C15);
+    let mut sched = TickScheduler::new(INTERVAL, policy, 0);
+    let (mut fired, mut missed, mut now) = (0u64, 0u64, 0u64);
+
+    while fired + missed < TICKS {
+        match sched.poll(now) {
+            Some(dropped) => {
+                fired += 1;
+                missed += dropped;
+            }
+            None => now = sched.next_deadline() + jitter.wakeup_delay(),
+        }
+    }
+    (fired, missed, now, sched)
+}
+
+#[test]
+fn catch_up_has_zero_cumulative_drift() {
+    let (fired, missed, now, sched) = simulate(MissPolicy::CatchUp { max_burst: 16 });
+    assert_eq!(missed, 0);
+    assert_eq!(fired, TICKS);
+    // Every slot up to `now` has fired and the next one sits exactly on the grid.
+    assert_eq!(sched.next_deadline(), TICKS * INTERVAL);
+    assert!(now < sched.next_deadline() + 3 * INTERVAL);
+}
+
+#[test]
+fn skip_stays_on_grid() {
+    let (fired, missed, _, sched) = simulate(MissPolicy::Skip);
+    assert!(missed > 0, "the jitter model must produce some stalls");
+    assert_eq!(fired + missed, TICKS);
+    assert_eq!(sched.next_deadline(), TICKS * INTERVAL);
+}
+
+#[test]
+fn catch_up_drops_beyond_burst() {
+    let mut sched = TickScheduler::new(INTERVAL, MissPolicy::CatchUp { max_burst: 2 }, 0);
+    assert_eq!(sched.poll(0), Some(0));
+    // Woke up 5 slots late: slots 1..=5 are due, only the last 2 are kept.
+    assert_eq!(sched.poll(5 * INTERVAL), Some(3));
+    assert_eq!(sched.poll(5 * INTERVAL), Some(0));
+    assert_eq!(sched.poll(5 * INTERVAL), None);
+    assert_eq!(sched.next_deadline(), 6 * INTERVAL);
+}
+
+#[test]
+fn interval_change_reanchors_grid() {
+    let mut sched = TickScheduler::new(INTERVAL, MissPolicy::Skip, 0);
+    assert_eq!(sched.poll(0), Some(0));
+    sched.set_interval(INTERVAL / 2, 300_000);
+    assert_eq!(sched.poll(300_000), Some(0));
+    assert_eq!(sched.next_deadline(), 800_000);
+}
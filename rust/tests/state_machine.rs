@@ -0,0 +1,70 @@
+use rustnithm::{ConnEvent, ConnState, Engine, IllegalTransition};
+
+const IDLE: ConnState = ConnState::Idle;
+const CONNECTED: ConnState = ConnState::Connected;
+const SYNC_ON: ConnState = ConnState::Syncing { target_connected: true };
+const SYNC_OFF: ConnState = ConnState::Syncing { target_connected: false };
+
+const TOGGLE: ConnEvent = ConnEvent::Toggle;
+const SYNC: ConnEvent = ConnEvent::SyncRequest;
+const CONFIRM_ON: ConnEvent = ConnEvent::ServerConfirm { connected: true };
+const CONFIRM_OFF: ConnEvent = ConnEvent::ServerConfirm { connected: false };
+const TIMEOUT: ConnEvent = ConnEvent::Timeout;
+const DISCONNECT: ConnEvent = ConnEvent::Disconnect;
+
+/// Every (state, event) pair; `None` marks an illegal transition.
+const TABLE: &[(ConnState, ConnEvent, Option<ConnState>)] = &[
+    (IDLE, TOGGLE, Some(CONNECTED)),
+    (IDLE, SYNC, Some(SYNC_ON)),
+    (IDLE, CONFIRM_ON, None),
+    (IDLE, CONFIRM_OFF, None),
+    (IDLE, TIMEOUT, None),
+    (IDLE, DISCONNECT, Some(IDLE)),
+    (CONNECTED, TOGGLE, Some(IDLE)),
+    (CONNECTED, SYNC, Some(SYNC_OFF)),
+    (CONNECTED, CONFIRM_ON, None),
+    (CONNECTED, CONFIRM_OFF, None),
+    (CONNECTED, TIMEOUT, None),
+    (CONNECTED, DISCONNECT, Some(IDLE)),
+    (SYNC_ON, TOGGLE, Some(IDLE)),
+    (SYNC_ON, SYNC, None),
+    (SYNC_ON, CONFIRM_ON, Some(CONNECTED)),
+    (SYNC_ON, CONFIRM_OFF, None),
+    (SYNC_ON, TIMEOUT, Some(IDLE)),
+    (SYNC_ON, DISCONNECT, Some(IDLE)),
+    (SYNC_OFF, TOGGLE, Some(IDLE)),
+    (SYNC_OFF, SYNC, None),
+    (SYNC_OFF, CONFIRM_ON, None),
+    (SYNC_OFF, CONFIRM_OFF, Some(IDLE)),
+    (SYNC_OFF, TIMEOUT, Some(CONNECTED)),
+    (SYNC_OFF, DISCONNECT, Some(IDLE)),
+];
+
+#[test]
+fn transition_table_is_complete() {
+    assert_eq!(TABLE.len(), 4 * 6);
+    for &(from, event, expected) in TABLE {
+        let got = from.next(event);
+        match expected {
+            Some(to) => assert_eq!(got, Ok(to), "{from:?} + {event:?}"),
+            None => assert_eq!(got, Err(IllegalTransition { from, event }), "{from:?} + {event:?}"),
+        }
+    }
+}
+
+#[test]
+fn codes_match_kotlin_contract() {
+    assert_eq!(IDLE.code(), 0);
+    assert_eq!(CONNECTED.code(), 1);
+    assert_eq!(SYNC_ON.code(), 2);
+    assert_eq!(SYNC_OFF.code(), 2);
+}
+
+#[test]
+fn engine_rejects_sync_while_syncing() {
+    let engine = Engine::new();
+    assert_eq!(engine.toggle_sync(), Ok(SYNC_ON));
+    assert!(engine.toggle_sync().is_err());
+    assert_eq!(engine.state(), SYNC_ON);
+    assert_eq!(engine.toggle_client(), Ok(IDLE));
+}